@@ -1,19 +1,27 @@
 mod renderer;
 
-use renderer::entity::Matrix;
+use renderer::entity::{EntityContext, Matrix};
 use renderer::*;
-use std::sync::Arc;
+use winit::VirtualKeyCode;
 
 struct TestEntity {
     hp: u64,
 }
 
-impl renderer::entity::Entity for TestEntity {
-    fn init(&mut self) {
-        println!("TestEntity init ({})", self.hp);
+impl renderer::entity::Entity<()> for TestEntity {
+    fn init(&mut self, ctx: &mut EntityContext<()>) {
+        println!("TestEntity {} init ({})", ctx.label(), self.hp);
     }
-    fn update(&mut self) {
-        println!("TestEntity update");
+    fn update(&mut self, ctx: &mut EntityContext<()>, dt: f32) {
+        if ctx.input.key_down(VirtualKeyCode::Left) {
+            ctx.matrix.pos.0 -= 0.5 * dt;
+        }
+        if ctx.input.key_down(VirtualKeyCode::Right) {
+            ctx.matrix.pos.0 += 0.5 * dt;
+        }
+        if ctx.input.key_pressed(VirtualKeyCode::Escape) {
+            ctx.quit();
+        }
     }
 }
 
@@ -24,9 +32,9 @@ fn main() {
         "test",
         Matrix::new((0.5, 0.5), (0.5, 0.5)),
         include_bytes!("test.png"),
-        Arc::new(TestEntity { hp: 100 }),
+        Box::new(TestEntity { hp: 100 }),
         true,
     );
 
-    let _vk = VkSession::run(game).unwrap();
+    let _state = VkSession::run(game).unwrap();
 }
@@ -0,0 +1,316 @@
+use crate::renderer::entity::{Matrix, Rect};
+use hashbrown::HashMap;
+
+// Frames cut out of a single image
+#[derive(Debug, Clone)]
+pub struct SpriteSheet {
+    frames: Vec<Rect>,
+}
+
+impl SpriteSheet {
+    // Equally sized frames laid out in a grid starting at the top-left corner, numbered row by row
+    pub fn grid(frame_size: (u32, u32), columns: u32, rows: u32) -> Self {
+        let mut frames = Vec::new();
+        for row in 0..rows {
+            for column in 0..columns {
+                frames.push(Rect::new(
+                    (column * frame_size.0) as f32,
+                    (row * frame_size.1) as f32,
+                    frame_size.0 as f32,
+                    frame_size.1 as f32,
+                ));
+            }
+        }
+        SpriteSheet { frames: frames }
+    }
+
+    // For sheets whose frames aren't on a grid
+    pub fn from_rects(frames: Vec<Rect>) -> Self {
+        SpriteSheet { frames: frames }
+    }
+
+    pub fn frame(&self, index: usize) -> Option<Rect> {
+        self.frames.get(index).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlayMode {
+    // Stops on the last frame
+    Once,
+    // Starts over from the first frame
+    Loop,
+    // Plays backwards once it reaches the last frame, then forwards again
+    PingPong,
+}
+
+// A sequence of sprite sheet frames, each shown for its own duration in seconds
+#[derive(Debug, Clone)]
+pub struct Clip {
+    frames: Vec<(usize, f32)>,
+    mode: PlayMode,
+}
+
+impl Clip {
+    // Every frame is shown for `frame_duration` seconds
+    pub fn new(frames: &[usize], frame_duration: f32, mode: PlayMode) -> Self {
+        let frames = frames
+            .iter()
+            .map(|&f| (f, frame_duration))
+            .collect::<Vec<_>>();
+        Clip::with_durations(&frames, mode)
+    }
+
+    pub fn with_durations(frames: &[(usize, f32)], mode: PlayMode) -> Self {
+        assert!(!frames.is_empty(), "A clip needs at least one frame");
+        assert!(
+            frames.iter().all(|f| f.1 > 0.0),
+            "Frame durations must be above 0"
+        );
+        Clip {
+            frames: frames.to_vec(),
+            mode: mode,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnimationEvent {
+    // A `PlayMode::Once` clip finished its last frame
+    Finished(String),
+    // A looping clip is back at its first frame
+    Looped(String),
+}
+
+// Where the animator is within the current clip
+#[derive(Debug, Clone)]
+struct Playback {
+    clip: String,
+    // Index into the clip's frames, not the sprite sheet
+    frame: usize,
+    forward: bool,
+    elapsed: f32,
+    finished: bool,
+}
+
+// Plays clips from a sprite sheet by pointing `Matrix::source` at the current frame. Meant to be
+// owned by an entity and advanced from `Entity::update`.
+#[derive(Debug, Clone)]
+pub struct Animator {
+    sheet: SpriteSheet,
+    clips: HashMap<String, Clip>,
+    playback: Option<Playback>,
+}
+
+impl Animator {
+    pub fn new(sheet: SpriteSheet) -> Self {
+        Animator {
+            sheet: sheet,
+            clips: HashMap::new(),
+            playback: None,
+        }
+    }
+
+    // Replacing the clip that's playing starts it over
+    pub fn add_clip(&mut self, name: &str, clip: Clip) {
+        let frames = self.sheet.len();
+        assert!(
+            clip.frames.iter().all(|f| f.0 < frames),
+            "Clip {} uses frames the sprite sheet doesn't have",
+            name
+        );
+        self.clips.insert(name.to_owned(), clip);
+        if self.current_clip() == Some(name) {
+            self.restart(name);
+        }
+    }
+
+    // Switches to a clip from its first frame. Playing the clip that's already playing does
+    // nothing, use `restart` for that.
+    pub fn play(&mut self, name: &str) {
+        if self.current_clip() != Some(name) {
+            self.restart(name);
+        }
+    }
+
+    pub fn restart(&mut self, name: &str) {
+        if !self.clips.contains_key(name) {
+            eprintln!("No animation clip named {}", name);
+            return;
+        }
+        self.playback = Some(Playback {
+            clip: name.to_owned(),
+            frame: 0,
+            forward: true,
+            elapsed: 0.0,
+            finished: false,
+        });
+    }
+
+    pub fn stop(&mut self) {
+        self.playback = None;
+    }
+
+    pub fn current_clip(&self) -> Option<&str> {
+        self.playback.as_ref().map(|p| p.clip.as_str())
+    }
+
+    // Whether a `PlayMode::Once` clip is done
+    pub fn is_finished(&self) -> bool {
+        self.playback.as_ref().map_or(false, |p| p.finished)
+    }
+
+    // Index into the sprite sheet of the frame being shown
+    pub fn frame(&self) -> Option<usize> {
+        let p = self.playback.as_ref()?;
+        Some(self.clips[&p.clip].frames[p.frame].0)
+    }
+
+    // Moves the current clip `dt` seconds ahead and shows its frame on `matrix`. Returns what
+    // happened along the way, possibly several events if `dt` spans whole clips.
+    pub fn update(&mut self, dt: f32, matrix: &mut Matrix) -> Vec<AnimationEvent> {
+        let mut events = Vec::new();
+        if let Some(p) = &mut self.playback {
+            let clip = &self.clips[&p.clip];
+            p.elapsed += dt;
+            while !p.finished && p.elapsed >= clip.frames[p.frame].1 {
+                p.elapsed -= clip.frames[p.frame].1;
+                if let Some(event) = p.advance(clip) {
+                    events.push(event);
+                }
+            }
+        }
+        self.apply(matrix);
+        events
+    }
+
+    // Shows the current frame without advancing
+    pub fn apply(&self, matrix: &mut Matrix) {
+        if let Some(frame) = self.frame() {
+            matrix.source = self.sheet.frame(frame);
+        }
+    }
+}
+
+impl Playback {
+    fn advance(&mut self, clip: &Clip) -> Option<AnimationEvent> {
+        let last = clip.frames.len() - 1;
+        match clip.mode {
+            PlayMode::Once => {
+                if self.frame == last {
+                    self.finished = true;
+                    self.elapsed = 0.0;
+                    return Some(AnimationEvent::Finished(self.clip.clone()));
+                }
+                self.frame += 1;
+            }
+            PlayMode::Loop => {
+                if self.frame == last {
+                    self.frame = 0;
+                    return Some(AnimationEvent::Looped(self.clip.clone()));
+                }
+                self.frame += 1;
+            }
+            PlayMode::PingPong => {
+                if last == 0 {
+                    return Some(AnimationEvent::Looped(self.clip.clone()));
+                }
+                if self.forward && self.frame == last {
+                    self.forward = false;
+                } else if !self.forward && self.frame == 0 {
+                    self.forward = true;
+                }
+                match self.forward {
+                    true => self.frame += 1,
+                    false => self.frame -= 1,
+                }
+                if self.frame == 0 {
+                    return Some(AnimationEvent::Looped(self.clip.clone()));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(animator: &mut Animator, steps: usize) -> Vec<usize> {
+        let mut matrix = Matrix::new((0.0, 0.0), (1.0, 1.0));
+        (0..steps)
+            .map(|_| {
+                animator.update(1.0, &mut matrix);
+                animator.frame().unwrap()
+            })
+            .collect()
+    }
+
+    fn animator(mode: PlayMode) -> Animator {
+        let mut animator = Animator::new(SpriteSheet::grid((16, 16), 4, 1));
+        animator.add_clip("walk", Clip::new(&[0, 1, 2], 1.0, mode));
+        animator.play("walk");
+        animator
+    }
+
+    #[test]
+    fn loop_starts_over() {
+        let mut animator = animator(PlayMode::Loop);
+        assert_eq!(frames(&mut animator, 6), vec![1, 2, 0, 1, 2, 0]);
+        assert!(!animator.is_finished());
+    }
+
+    #[test]
+    fn ping_pong_turns_around_at_both_ends() {
+        let mut animator = animator(PlayMode::PingPong);
+        assert_eq!(frames(&mut animator, 6), vec![1, 2, 1, 0, 1, 2]);
+    }
+
+    #[test]
+    fn once_stops_on_the_last_frame() {
+        let mut animator = animator(PlayMode::Once);
+        let mut matrix = Matrix::new((0.0, 0.0), (1.0, 1.0));
+        assert_eq!(animator.update(2.5, &mut matrix), vec![]);
+        assert_eq!(
+            animator.update(1.0, &mut matrix),
+            vec![AnimationEvent::Finished("walk".to_owned())]
+        );
+        assert!(animator.is_finished());
+        assert_eq!(animator.frame(), Some(2));
+        assert_eq!(matrix.source, Some(Rect::new(32.0, 0.0, 16.0, 16.0)));
+    }
+
+    #[test]
+    fn long_steps_report_every_loop() {
+        let mut animator = animator(PlayMode::Loop);
+        let mut matrix = Matrix::new((0.0, 0.0), (1.0, 1.0));
+        let events = animator.update(6.0, &mut matrix);
+        assert_eq!(events.len(), 2);
+        assert_eq!(animator.frame(), Some(0));
+    }
+
+    #[test]
+    fn replacing_the_playing_clip_restarts_it() {
+        let mut animator = animator(PlayMode::Loop);
+        frames(&mut animator, 2);
+        animator.add_clip("walk", Clip::new(&[3], 1.0, PlayMode::Loop));
+        assert_eq!(animator.frame(), Some(3));
+        assert_eq!(frames(&mut animator, 2), vec![3, 3]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn clips_are_checked_against_the_sheet() {
+        let mut animator = Animator::new(SpriteSheet::grid((16, 16), 2, 1));
+        animator.add_clip("walk", Clip::new(&[0, 2], 1.0, PlayMode::Loop));
+    }
+}
@@ -0,0 +1,382 @@
+use image::RgbaImage;
+use std::fmt;
+
+// Where an image ended up, in pixels within its page. Doesn't include the padding.
+#[derive(Debug, Clone)]
+pub struct AtlasEntry {
+    pub label: String,
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct AtlasLayout {
+    pub page_size: u32,
+    pub padding: u32,
+    pub pages: usize,
+    pub entries: Vec<AtlasEntry>,
+    // Images that didn't fit on a page, these get a texture of their own
+    pub skipped: Vec<String>,
+}
+
+impl AtlasLayout {
+    pub fn new(page_size: u32, padding: u32) -> Self {
+        AtlasLayout {
+            page_size: page_size,
+            padding: padding,
+            pages: 0,
+            entries: Vec::new(),
+            skipped: Vec::new(),
+        }
+    }
+
+    // Adds the pages of another layout with the same page size after this one's
+    pub fn append(&mut self, other: AtlasLayout) {
+        let offset = self.pages;
+        self.entries.extend(other.entries.into_iter().map(|e| AtlasEntry {
+            page: e.page + offset,
+            ..e
+        }));
+        self.skipped.extend(other.skipped);
+        self.pages += other.pages;
+    }
+
+    // Texture coordinates of an entry within its page, as (u0, v0, u1, v1)
+    pub fn uv_rect(&self, entry: &AtlasEntry) -> [f32; 4] {
+        let size = self.page_size as f32;
+        [
+            entry.x as f32 / size,
+            entry.y as f32 / size,
+            (entry.x + entry.width) as f32 / size,
+            (entry.y + entry.height) as f32 / size,
+        ]
+    }
+}
+
+impl fmt::Display for AtlasLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Atlas: {} page(s) of {}x{}, {}px padding",
+            self.pages, self.page_size, self.page_size, self.padding
+        )?;
+        for e in &self.entries {
+            writeln!(
+                f,
+                "  [{}] {} at ({}, {}) {}x{}",
+                e.page, e.label, e.x, e.y, e.width, e.height
+            )?;
+        }
+        for label in &self.skipped {
+            writeln!(f, "  {} is too large, not packed", label)?;
+        }
+        Ok(())
+    }
+}
+
+// Packs images into square pages using shelves, tallest images first.
+//
+// Every image is surrounded by `padding` pixels copied from its own edges, so filtering near the
+// border never picks up a neighbour.
+pub struct AtlasBuilder {
+    page_size: u32,
+    padding: u32,
+    images: Vec<(String, RgbaImage)>,
+}
+
+// Free space on a page, shelves are only ever appended at the bottom
+struct Page {
+    shelf_y: u32,
+    shelf_height: u32,
+    cursor_x: u32,
+}
+
+impl AtlasBuilder {
+    pub fn new(page_size: u32, padding: u32) -> Self {
+        AtlasBuilder {
+            page_size: page_size,
+            padding: padding,
+            images: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, label: &str, image: RgbaImage) {
+        self.images.push((label.to_owned(), image));
+    }
+
+    // Returns the pages along with where every image went. Skipped images are handed back as is.
+    pub fn build(self) -> (Vec<RgbaImage>, AtlasLayout, Vec<(String, RgbaImage)>) {
+        let page_size = self.page_size;
+        let padding = self.padding;
+
+        let mut images = self.images;
+        images.sort_by(|a, b| b.1.height().cmp(&a.1.height()));
+
+        let mut pages: Vec<(Page, RgbaImage)> = Vec::new();
+        let mut layout = AtlasLayout::new(page_size, padding);
+        let mut skipped = Vec::new();
+
+        for (label, image) in images {
+            let w = image.width() + padding * 2;
+            let h = image.height() + padding * 2;
+            if w > page_size || h > page_size || image.width() == 0 || image.height() == 0 {
+                layout.skipped.push(label.clone());
+                skipped.push((label, image));
+                continue;
+            }
+
+            let spot = pages
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(i, (page, _))| page.place(w, h, page_size).map(|p| (i, p)))
+                .next();
+            let (index, (x, y)) = match spot {
+                Some(spot) => spot,
+                None => {
+                    let mut page = Page::new();
+                    let p = page.place(w, h, page_size).unwrap();
+                    pages.push((page, RgbaImage::new(page_size, page_size)));
+                    (pages.len() - 1, p)
+                }
+            };
+
+            blit_padded(&mut pages[index].1, &image, x, y, padding);
+            layout.entries.push(AtlasEntry {
+                label: label,
+                page: index,
+                x: x + padding,
+                y: y + padding,
+                width: image.width(),
+                height: image.height(),
+            });
+        }
+
+        layout.pages = pages.len();
+        (
+            pages.into_iter().map(|(_, img)| img).collect(),
+            layout,
+            skipped,
+        )
+    }
+}
+
+impl Page {
+    fn new() -> Self {
+        Page {
+            shelf_y: 0,
+            shelf_height: 0,
+            cursor_x: 0,
+        }
+    }
+
+    // Reserves a w*h area, returning its top-left corner
+    fn place(&mut self, w: u32, h: u32, page_size: u32) -> Option<(u32, u32)> {
+        if self.cursor_x + w <= page_size && h <= self.shelf_height {
+            let x = self.cursor_x;
+            self.cursor_x += w;
+            return Some((x, self.shelf_y));
+        }
+        // Images come in tallest first, so a new shelf is as tall as the first image on it
+        let next_y = self.shelf_y + self.shelf_height;
+        if next_y + h <= page_size && w <= page_size {
+            self.shelf_y = next_y;
+            self.shelf_height = h;
+            self.cursor_x = w;
+            return Some((0, next_y));
+        }
+        None
+    }
+}
+
+// Free space on a square page, kept as the lowest free row along every stretch of its width.
+// Unlike `Page` it doesn't need the tallest images first, so it packs the glyph cache, which gets
+// glyphs in whatever order text needs them.
+pub(crate) struct Skyline {
+    size: u32,
+    // (x, y, width) from left to right, covering the whole page width
+    segments: Vec<(u32, u32, u32)>,
+}
+
+impl Skyline {
+    pub(crate) fn new(size: u32) -> Self {
+        Skyline {
+            size: size,
+            segments: vec![(0, 0, size)],
+        }
+    }
+
+    // Reserves a w*h area as high up as it fits, returning its top-left corner
+    pub(crate) fn place(&mut self, w: u32, h: u32) -> Option<(u32, u32)> {
+        // Highest spot, then the one wasting the least width
+        let (i, y) = (0..self.segments.len())
+            .filter_map(|i| self.fits(i, w, h).map(|y| (i, y)))
+            .min_by_key(|&(i, y)| (y, self.segments[i].2))?;
+        let x = self.segments[i].0;
+
+        // Cuts the segments below the new area short, or removes them
+        let end = x + w;
+        self.segments.insert(i, (x, y + h, w));
+        while let Some(&(sx, sy, sw)) = self.segments.get(i + 1) {
+            if sx + sw <= end {
+                self.segments.remove(i + 1);
+            } else {
+                self.segments[i + 1] = (end, sy, sx + sw - end);
+                break;
+            }
+        }
+
+        // Neighbours at the same height become one
+        let mut j = 0;
+        while j + 1 < self.segments.len() {
+            let (a, b) = (self.segments[j], self.segments[j + 1]);
+            if a.1 == b.1 {
+                self.segments[j].2 += b.2;
+                self.segments.remove(j + 1);
+            } else {
+                j += 1;
+            }
+        }
+        Some((x, y))
+    }
+
+    // How far down a w*h area starting at segment `i` has to go, None if it doesn't fit
+    fn fits(&self, i: usize, w: u32, h: u32) -> Option<u32> {
+        let x = self.segments[i].0;
+        if w == 0 || h == 0 || x + w > self.size {
+            return None;
+        }
+        let mut y = 0;
+        let mut covered = 0;
+        for &(_, sy, sw) in &self.segments[i..] {
+            y = y.max(sy);
+            covered += sw;
+            if covered >= w {
+                break;
+            }
+        }
+        match y + h <= self.size {
+            true => Some(y),
+            false => None,
+        }
+    }
+}
+
+// Copies `src` to (x + padding, y + padding), extending its edge pixels into the padding
+fn blit_padded(dst: &mut RgbaImage, src: &RgbaImage, x: u32, y: u32, padding: u32) {
+    let (w, h) = src.dimensions();
+    for py in 0..h + padding * 2 {
+        for px in 0..w + padding * 2 {
+            let sx = px.saturating_sub(padding).min(w - 1);
+            let sy = py.saturating_sub(padding).min(h - 1);
+            dst.put_pixel(x + px, y + py, *src.get_pixel(sx, sy));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlaps(a: (u32, u32, u32, u32), b: (u32, u32, u32, u32)) -> bool {
+        a.0 < b.0 + b.2 && b.0 < a.0 + a.2 && a.1 < b.1 + b.3 && b.1 < a.1 + a.3
+    }
+
+    fn image(w: u32, h: u32, shade: u8) -> RgbaImage {
+        RgbaImage::from_pixel(w, h, image::Rgba([shade, shade, shade, 255]))
+    }
+
+    #[test]
+    fn shelves_pack_without_overlap() {
+        let mut builder = AtlasBuilder::new(64, 1);
+        for i in 0..12 {
+            builder.add(&i.to_string(), image(5 + i * 3 % 11, 4 + i * 5 % 9, i as u8));
+        }
+        let (pages, layout, skipped) = builder.build();
+        assert_eq!(pages.len(), layout.pages);
+        assert!(skipped.is_empty());
+        assert_eq!(layout.entries.len(), 12);
+        for (i, a) in layout.entries.iter().enumerate() {
+            // Padding included
+            let rect = |e: &AtlasEntry| (e.x - 1, e.y - 1, e.width + 2, e.height + 2);
+            let r = rect(a);
+            assert!(r.0 + r.2 <= 64 && r.1 + r.3 <= 64);
+            for b in &layout.entries[i + 1..] {
+                assert!(a.page != b.page || !overlaps(r, rect(b)));
+            }
+            let shade = a.label.parse::<u8>().unwrap();
+            assert_eq!(pages[a.page].get_pixel(a.x, a.y)[0], shade);
+        }
+    }
+
+    #[test]
+    fn padding_repeats_the_edges() {
+        let mut builder = AtlasBuilder::new(16, 2);
+        let mut img = image(2, 2, 10);
+        img.put_pixel(1, 1, image::Rgba([200, 200, 200, 255]));
+        builder.add("a", img);
+        let (pages, layout, _) = builder.build();
+        let e = &layout.entries[0];
+        assert_eq!((e.x, e.y), (2, 2));
+        assert_eq!(pages[0].get_pixel(0, 0)[0], 10);
+        assert_eq!(pages[0].get_pixel(5, 5)[0], 200);
+        assert_eq!(pages[0].get_pixel(5, 0)[0], 10);
+    }
+
+    #[test]
+    fn full_pages_start_a_new_one() {
+        let mut builder = AtlasBuilder::new(16, 0);
+        for i in 0..3 {
+            builder.add(&i.to_string(), image(16, 8, 0));
+        }
+        builder.add("huge", image(17, 1, 0));
+        let (pages, layout, skipped) = builder.build();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(layout.skipped, vec!["huge".to_owned()]);
+        assert_eq!(skipped[0].0, "huge");
+        let uv = layout.uv_rect(&layout.entries[1]);
+        assert_eq!(uv, [0.0, 0.5, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn skyline_packs_any_order_without_overlap() {
+        let mut skyline = Skyline::new(32);
+        let mut placed: Vec<(u32, u32, u32, u32)> = Vec::new();
+        // Sizes all over the place, neither sorted by width nor height
+        for (w, h) in (0..30).map(|i| (i * 7 % 11 + 1, i * 5 % 13 + 1)) {
+            let (x, y) = match skyline.place(w, h) {
+                Some(spot) => spot,
+                None => continue,
+            };
+            let rect = (x, y, w, h);
+            assert!(x + w <= 32 && y + h <= 32, "{:?} is off the page", rect);
+            assert!(
+                placed.iter().all(|&p| !overlaps(p, rect)),
+                "{:?} overlaps",
+                rect
+            );
+            placed.push(rect);
+        }
+        assert!(placed.len() > 20);
+    }
+
+    #[test]
+    fn skyline_fills_gaps_left_by_taller_neighbours() {
+        let mut skyline = Skyline::new(8);
+        assert_eq!(skyline.place(4, 8), Some((0, 0)));
+        assert_eq!(skyline.place(4, 2), Some((4, 0)));
+        assert_eq!(skyline.place(4, 6), Some((4, 2)));
+        assert_eq!(skyline.place(1, 1), None);
+    }
+
+    #[test]
+    fn skyline_rejects_what_doesnt_fit() {
+        let mut skyline = Skyline::new(8);
+        assert_eq!(skyline.place(9, 1), None);
+        assert_eq!(skyline.place(1, 9), None);
+        assert_eq!(skyline.place(0, 1), None);
+        assert_eq!(skyline.place(8, 8), Some((0, 0)));
+    }
+}
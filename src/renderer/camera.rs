@@ -0,0 +1,166 @@
+use crate::renderer::entity::Rect;
+use crate::renderer::transform::Transform;
+
+// Decides which part of the world ends up on the screen.
+//
+// Positions are in `Units::Normalized`, so with the camera centered on (0.5, 0.5) and no zoom or
+// rotation (the default) one unit is exactly the size of the screen, whatever units textures use.
+#[derive(Debug, Clone)]
+pub struct Camera {
+    // World position shown in the middle of the screen
+    pub center: (f32, f32),
+    // 2.0 makes everything twice as large
+    pub zoom: f32,
+    // Clockwise, in radians
+    pub rotation: f32,
+    // Area the view is kept within while following or after `clamp_to_bounds`, rotation aside
+    pub bounds: Option<Rect>,
+    // Label of the entity to keep centered, and how many seconds it takes to catch up to about
+    // two thirds of the way
+    follow: Option<(String, f32)>,
+    // Size of the screen in the units textures use: the virtual resolution if there is one,
+    // otherwise the window in physical pixels. Kept up to date by the renderer for the aspect
+    // ratio.
+    pub(crate) screen: (f32, f32),
+}
+
+impl Camera {
+    pub fn new() -> Self {
+        Camera {
+            center: (0.5, 0.5),
+            zoom: 1.0,
+            rotation: 0.0,
+            bounds: None,
+            follow: None,
+            screen: (1.0, 1.0),
+        }
+    }
+
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        self.center.0 += dx;
+        self.center.1 += dy;
+    }
+
+    // Keeps the entity's texture centered. A `smoothing` of 0 snaps right to it every update.
+    pub fn follow(&mut self, label: &str, smoothing: f32) {
+        self.follow = Some((label.to_owned(), smoothing.max(0.0)));
+    }
+
+    pub fn stop_following(&mut self) {
+        self.follow = None;
+    }
+
+    pub fn following(&self) -> Option<&str> {
+        self.follow.as_ref().map(|f| f.0.as_str())
+    }
+
+    // Moves towards `target` as if it were being followed for `dt` seconds
+    pub(crate) fn track(&mut self, target: (f32, f32), dt: f32) {
+        let smoothing = match &self.follow {
+            Some(f) => f.1,
+            None => return,
+        };
+        let t = match smoothing > 0.0 {
+            true => 1.0 - (-dt / smoothing).exp(),
+            false => 1.0,
+        };
+        self.center.0 += (target.0 - self.center.0) * t;
+        self.center.1 += (target.1 - self.center.1) * t;
+        self.clamp_to_bounds();
+    }
+
+    // Moves the center so that the view doesn't show anything outside of `bounds`. Bounds smaller
+    // than the view are centered instead.
+    pub fn clamp_to_bounds(&mut self) {
+        let bounds = match self.bounds {
+            Some(b) => b,
+            None => return,
+        };
+        let half = (0.5 / self.zoom, 0.5 / self.zoom);
+        let clamp = |c: f32, half: f32, start: f32, size: f32| match size > half * 2.0 {
+            true => c.max(start + half).min(start + size - half),
+            false => start + size / 2.0,
+        };
+        self.center = (
+            clamp(self.center.0, half.0, bounds.x, bounds.width),
+            clamp(self.center.1, half.1, bounds.y, bounds.height),
+        );
+    }
+
+    // Maps world positions to pixels on a screen of the given size
+    pub fn view(&self, screen: (f32, f32)) -> Transform {
+        Transform::translate(screen.0 / 2.0, screen.1 / 2.0)
+            * Transform::rotate(-self.rotation)
+            * Transform::scale(self.zoom, self.zoom)
+            * Transform::translate(-self.center.0 * screen.0, -self.center.1 * screen.1)
+            * Transform::scale(screen.0, screen.1)
+    }
+
+    // Where a point on the screen, in 0..1 of the drawn area like `Input::mouse_pos`, is in the
+    // world
+    pub fn screen_to_world(&self, pos: (f32, f32)) -> (f32, f32) {
+        let screen = self.screen;
+        self.view(screen)
+            .inverse()
+            .apply((pos.0 * screen.0, pos.1 * screen.1))
+    }
+
+    pub fn world_to_screen(&self, pos: (f32, f32)) -> (f32, f32) {
+        let screen = self.screen;
+        let p = self.view(screen).apply(pos);
+        (p.0 / screen.0, p.1 / screen.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4
+    }
+
+    fn camera() -> Camera {
+        let mut camera = Camera::new();
+        camera.screen = (1280.0, 720.0);
+        camera
+    }
+
+    #[test]
+    fn default_camera_shows_the_screen_as_is() {
+        let camera = camera();
+        for &p in &[(0.0, 0.0), (0.25, 0.75), (1.0, 1.0)] {
+            assert!(close(camera.screen_to_world(p), p));
+        }
+    }
+
+    #[test]
+    fn screen_to_world_round_trips() {
+        let mut camera = camera();
+        camera.center = (3.0, -2.0);
+        camera.zoom = 2.5;
+        camera.rotation = 1.2;
+        for &p in &[(0.0, 0.0), (0.5, 0.5), (0.1, 0.9), (1.0, 0.3)] {
+            assert!(close(camera.world_to_screen(camera.screen_to_world(p)), p));
+        }
+        assert!(close(camera.screen_to_world((0.5, 0.5)), camera.center));
+    }
+
+    #[test]
+    fn zoom_shows_less_of_the_world() {
+        let mut camera = camera();
+        camera.zoom = 2.0;
+        assert!(close(camera.screen_to_world((0.0, 0.0)), (0.25, 0.25)));
+        assert!(close(camera.screen_to_world((1.0, 1.0)), (0.75, 0.75)));
+    }
+
+    #[test]
+    fn clamps_to_bounds() {
+        let mut camera = camera();
+        camera.bounds = Some(Rect::new(0.0, 0.0, 4.0, 0.5));
+        camera.center = (-1.0, 3.0);
+        camera.clamp_to_bounds();
+        // Bounds shorter than the view are centered on
+        assert!(close(camera.center, (0.5, 0.25)));
+    }
+}
@@ -0,0 +1,62 @@
+use image::RgbaImage;
+
+// Images are stored and uploaded sRGB encoded, but blending and filtering have to happen in
+// linear light to come out right once the GPU decodes them
+
+pub fn to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    match c <= 0.04045 {
+        true => c / 12.92,
+        false => ((c + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+pub fn to_srgb(c: f32) -> u8 {
+    let c = c.max(0.0).min(1.0);
+    let c = match c <= 0.003_130_8 {
+        true => c * 12.92,
+        false => 1.055 * c.powf(1.0 / 2.4) - 0.055,
+    };
+    (c * 255.0).round() as u8
+}
+
+// Multiplies every color with its alpha in linear space, so sampling the sRGB texture gives
+// back the linear color times alpha that blending expects
+pub fn premultiply(img: &mut RgbaImage) {
+    let linear = (0..=255).map(to_linear).collect::<Vec<_>>();
+    for p in img.pixels_mut() {
+        let a = p[3];
+        if a == 255 {
+            continue;
+        }
+        for c in 0..3 {
+            p[c] = to_srgb(linear[p[c] as usize] * a as f32 / 255.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn round_trips_every_byte() {
+        for c in 0..=255 {
+            assert_eq!(to_srgb(to_linear(c)), c);
+        }
+    }
+
+    #[test]
+    fn premultiplies_in_linear_space() {
+        let mut img = RgbaImage::from_pixel(1, 1, Rgba([255, 128, 0, 128]));
+        premultiply(&mut img);
+        let p = img.get_pixel(0, 0);
+        // Half of full intensity is 0.5 in linear light, which is about 188 once encoded
+        assert_eq!(p[0], to_srgb(128.0 / 255.0));
+        assert!(p[0] > 180);
+        assert!((to_linear(p[1]) - to_linear(128) * 128.0 / 255.0).abs() < 0.005);
+        assert_eq!(p[2], 0);
+        assert_eq!(p[3], 128);
+    }
+}
@@ -1,115 +1,866 @@
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
 
-use crate::renderer::main::draw;
+use crate::renderer::camera::Camera;
+use crate::renderer::color;
+use crate::renderer::input::Input;
+use crate::renderer::mipmap;
+use crate::renderer::options::TextureOptions;
+use crate::renderer::shape::Shape;
+use crate::renderer::text::{GlyphQuad, Text};
+use crate::renderer::transform::Transform;
 use crate::renderer::vertex::Vertex;
 use crate::renderer::DrawGraphicsPipeline;
-use image::GenericImageView;
+use image::RgbaImage;
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBuffer};
 use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
 use vulkano::descriptor::DescriptorSet;
 use vulkano::device;
 use vulkano::format::Format;
-use vulkano::image::{Dimensions, ImmutableImage};
+use vulkano::image::{Dimensions, ImageLayout, ImageUsage, ImmutableImage, MipmapsCount};
+use vulkano::sampler::Sampler;
 
-pub trait Entity {
-    fn init(&mut self); // TODO: These should also have a matrix as param
-    fn update(&mut self); // TODO: I can also give them `game` to fork
+pub trait Entity<S> {
+    fn init(&mut self, ctx: &mut EntityContext<S>);
+    // `dt` is the fixed time between two updates, in seconds
+    fn update(&mut self, ctx: &mut EntityContext<S>, dt: f32);
+    // Called once when despawned or after the game loop has stopped
+    fn teardown(&mut self) {}
+}
+
+// Everything an entity may touch while it's being initialized or updated
+pub struct EntityContext<'a, S> {
+    // The matrix of the texture this entity is connected to
+    pub matrix: &'a mut Matrix,
+    pub state: &'a mut S,
+    pub input: &'a Input,
+    pub camera: &'a mut Camera,
+    pub(crate) label: &'a str,
+    pub(crate) commands: &'a mut Vec<Command<S>>,
+}
+
+impl<'a, S> EntityContext<'a, S> {
+    pub fn label(&self) -> &str {
+        self.label
+    }
+
+    // Connects a new entity once the current update is done, see `Game::connect`
+    pub fn spawn(
+        &mut self,
+        label: &str,
+        matrix: Matrix,
+        img: &[u8],
+        entity: Box<Entity<S> + Send>,
+        enabled: bool,
+    ) {
+        self.spawn_with(label, matrix, img, entity, enabled, TextureOptions::default());
+    }
+
+    // See `Game::connect_with`
+    pub fn spawn_with(
+        &mut self,
+        label: &str,
+        matrix: Matrix,
+        img: &[u8],
+        entity: Box<Entity<S> + Send>,
+        enabled: bool,
+        options: TextureOptions,
+    ) {
+        self.commands.push(Command::Spawn {
+            label: label.to_owned(),
+            matrix: matrix,
+            img: img.to_vec(),
+            entity: entity,
+            enabled: enabled,
+            options: options,
+        });
+    }
+
+    // Removes an entity (possibly this one) once the current update is done
+    pub fn despawn(&mut self, label: &str) {
+        self.commands.push(Command::Despawn(label.to_owned()));
+    }
+
+    // See `Game::attach`
+    pub fn attach(&mut self, child: &str, parent: &str) {
+        self.commands.push(Command::Attach {
+            child: child.to_owned(),
+            parent: parent.to_owned(),
+        });
+    }
+
+    pub fn detach(&mut self, child: &str) {
+        self.commands.push(Command::Detach(child.to_owned()));
+    }
+
+    pub fn enable(&mut self, label: &str) {
+        self.commands.push(Command::Enable(label.to_owned()));
+    }
+
+    pub fn disable(&mut self, label: &str) {
+        self.commands.push(Command::Disable(label.to_owned()));
+    }
+
+    // See `Game::set_shape`
+    pub fn set_shape(&mut self, label: &str, shape: Shape) {
+        self.commands.push(Command::SetShape {
+            label: label.to_owned(),
+            shape: shape,
+        });
+    }
+
+    pub fn remove_shape(&mut self, label: &str) {
+        self.commands.push(Command::RemoveShape(label.to_owned()));
+    }
+
+    // Connects a new text entity once the current update is done, see `Game::connect_text`
+    pub fn spawn_text(
+        &mut self,
+        label: &str,
+        matrix: Matrix,
+        text: Text,
+        entity: Box<Entity<S> + Send>,
+        enabled: bool,
+    ) {
+        self.commands.push(Command::SpawnText {
+            label: label.to_owned(),
+            matrix: matrix,
+            text: text,
+            entity: entity,
+            enabled: enabled,
+        });
+    }
+
+    // See `Game::set_text`
+    pub fn set_text(&mut self, label: &str, text: Text) {
+        self.commands.push(Command::SetText {
+            label: label.to_owned(),
+            text: text,
+        });
+    }
+
+    pub fn quit(&mut self) {
+        self.commands.push(Command::Quit);
+    }
+}
+
+// Changes to the game requested by entities, applied between updates
+pub(crate) enum Command<S> {
+    Spawn {
+        label: String,
+        matrix: Matrix,
+        img: Vec<u8>,
+        entity: Box<Entity<S> + Send>,
+        enabled: bool,
+        options: TextureOptions,
+    },
+    SpawnText {
+        label: String,
+        matrix: Matrix,
+        text: Text,
+        entity: Box<Entity<S> + Send>,
+        enabled: bool,
+    },
+    Despawn(String),
+    Attach { child: String, parent: String },
+    Detach(String),
+    Enable(String),
+    Disable(String),
+    SetShape { label: String, shape: Shape },
+    RemoveShape(String),
+    SetText { label: String, text: Text },
+    Quit,
 }
 
 pub struct Texture {
     pub unloaded: Vec<u8>,
-    pub entity: Arc<Entity>,
     pub matrix: Matrix,
     pub loaded: Option<Arc<DescriptorSet + Send + Sync>>,
     pub waiter: Option<TextureLoadAwait>,
     pub dimensions: (u32, u32),
+    // Makes `matrix` relative to the parent's, see `Game::attach`
+    pub parent: Option<Weak<Mutex<Texture>>>,
+    pub vertex_cache: Option<VertexCache>,
+    // Where the image is within the loaded texture, as (u0, v0, u1, v1). Only smaller than the
+    // whole texture when it was packed into an atlas, see `Matrix::source` for drawing part of
+    // the image.
+    pub uv_rect: [f32; 4],
+    pub options: TextureOptions,
+    // Drawn instead of an image for entities connected with `Game::connect_text`. The renderer
+    // lays it out into `glyphs` and sets `dimensions` to the size of the block.
+    pub text: Option<Text>,
+    pub(crate) glyphs: Vec<GlyphQuad>,
+    // Textures are numbered in the order they're connected, later ones are drawn on top of
+    // earlier ones they'd otherwise tie with when sorting
+    pub order: usize,
 }
 
+// How many frames in a row a texture has to stay the same before it counts as static
+const STATIC_AFTER_FRAMES: u32 = 30;
+
+static NEXT_REVISION: AtomicUsize = AtomicUsize::new(0);
+
+// Vertices from `Texture::to_vert`, along with everything they were built from
+pub struct VertexCache {
+    matrix: Matrix,
+    dimensions: (u32, u32),
+    uv_rect: [f32; 4],
+    screen: (u32, u32),
+    parent: Option<Transform>,
+    // One for an image, one per glyph for text
+    pub quads: Vec<[Vertex; 4]>,
+    // Unique across all textures, changes whenever `quad` is rebuilt
+    pub revision: usize,
+    unchanged_frames: u32,
+}
+
+impl VertexCache {
+    pub fn is_static(&self) -> bool {
+        self.unchanged_frames >= STATIC_AFTER_FRAMES
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Matrix {
+    // Top-left corner of the untransformed texture
     pub pos: (f32, f32),
     pub size: (f32, f32),
+    // Ignores `size` and draws the texture at the size of its image (or `source`) in pixels
+    pub image_size: bool,
+    // What `pos` and `size` are measured in
+    pub units: Units,
+    // Higher layers are drawn on top of lower ones
+    pub layer: i32,
+    // Clockwise, in radians
+    pub rotation: f32,
+    pub scale: (f32, f32),
+    // Point that rotation, scale and skew happen around, relative to `size` (0.5, 0.5 is the
+    // center)
+    pub pivot: (f32, f32),
+    // Mirrors the texture horizontally and/or vertically, in place
+    pub flip: (bool, bool),
+    // Shear angles along the x and y axis, in radians
+    pub skew: (f32, f32),
+    // Part of the image to draw, the whole image if `None`
+    pub source: Option<Rect>,
+    // RGBA multiplied with every pixel, white leaves the image as is. The alpha fades the
+    // texture out.
+    pub color: [f32; 4],
+    pub blend: BlendMode,
+    // Positions the texture on the screen instead of in the world, so the camera doesn't move it.
+    // For HUDs and menus.
+    pub screen_space: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Units {
+    // Fractions of the screen, (1.0, 1.0) being all of it
+    Normalized,
+    // Pixels on the screen, or virtual pixels with a virtual resolution
+    Pixels,
+}
+
+// How a texture is combined with what's already been drawn below it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    // Regular transparency
+    Alpha,
+    // Adds the texture's color, for lights and particles
+    Additive,
+    // Darkens what's below, for shadows
+    Multiply,
+    // Lightens what's below, the inverse of `Multiply`
+    Screen,
+}
+
+// Area within an image, in pixels from its top-left corner
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Rect {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+        }
+    }
 }
 
 impl Matrix {
+    // `pos` and `size` in `Units::Normalized`
     pub fn new(pos: (f32, f32), size: (f32, f32)) -> Self {
         Matrix {
             pos: pos,
             size: size,
+            image_size: false,
+            units: Units::Normalized,
+            layer: 0,
+            rotation: 0.0,
+            scale: (1.0, 1.0),
+            pivot: (0.5, 0.5),
+            flip: (false, false),
+            skew: (0.0, 0.0),
+            source: None,
+            color: [1.0, 1.0, 1.0, 1.0],
+            blend: BlendMode::Alpha,
+            screen_space: false,
+        }
+    }
+
+    // `pos` in `Units::Pixels`, drawn at the size of the image
+    pub fn pixels(pos: (f32, f32)) -> Self {
+        Matrix {
+            units: Units::Pixels,
+            ..Matrix::new(pos, (0.0, 0.0))
+        }
+        .with_image_size()
+    }
+
+    pub fn with_size(mut self, size: (f32, f32)) -> Self {
+        self.size = size;
+        self.image_size = false;
+        self
+    }
+
+    pub fn with_image_size(mut self) -> Self {
+        self.image_size = true;
+        self
+    }
+
+    pub fn with_layer(mut self, layer: i32) -> Self {
+        self.layer = layer;
+        self
+    }
+
+    pub fn with_source(mut self, source: Rect) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn in_screen_space(mut self) -> Self {
+        self.screen_space = true;
+        self
+    }
+
+    // Only changes the alpha of `color`
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.color[3] = opacity;
+    }
+
+    fn to_pixels(&self, v: (f32, f32), screen: (f32, f32)) -> (f32, f32) {
+        match self.units {
+            Units::Normalized => (v.0 * screen.0, v.1 * screen.1),
+            Units::Pixels => v,
+        }
+    }
+
+    pub fn pixel_pos(&self, screen: (f32, f32)) -> (f32, f32) {
+        self.to_pixels(self.pos, screen)
+    }
+
+    // Size of the untransformed texture in pixels, `image` being the size it defaults to
+    pub fn pixel_size(&self, screen: (f32, f32), image: (f32, f32)) -> (f32, f32) {
+        match self.image_size {
+            true => image,
+            false => self.to_pixels(self.size, screen),
         }
     }
+
+    // Maps pixels within the untransformed texture, (0, 0) being its top-left corner, to pixels
+    // on the screen.
+    pub fn transform(&self, screen: (f32, f32), image: (f32, f32)) -> Transform {
+        let pos = self.pixel_pos(screen);
+        let size = self.pixel_size(screen, image);
+        let pivot = (self.pivot.0 * size.0, self.pivot.1 * size.1);
+        let center = (size.0 / 2.0, size.1 / 2.0);
+        let flip = (
+            if self.flip.0 { -1.0 } else { 1.0 },
+            if self.flip.1 { -1.0 } else { 1.0 },
+        );
+
+        Transform::translate(pos.0 + pivot.0, pos.1 + pivot.1)
+            * Transform::rotate(self.rotation)
+            * Transform::skew(self.skew.0, self.skew.1)
+            * Transform::scale(self.scale.0, self.scale.1)
+            * Transform::translate(center.0 - pivot.0, center.1 - pivot.1)
+            * Transform::scale(flip.0, flip.1)
+            * Transform::translate(-center.0, -center.1)
+    }
 }
 
 impl Texture {
+    // Nothing is drawn until the renderer has loaded it
+    pub fn new(img: Vec<u8>, matrix: Matrix, options: TextureOptions) -> Self {
+        Texture {
+            unloaded: img,
+            matrix: matrix,
+            // Known once the image is decoded
+            dimensions: (0, 0),
+            loaded: None,
+            waiter: None,
+            parent: None,
+            vertex_cache: None,
+            uv_rect: [0.0, 0.0, 1.0, 1.0],
+            options: options,
+            text: None,
+            glyphs: Vec::new(),
+            // Set when it's connected
+            order: 0,
+        }
+    }
+
+    // Like `Matrix::transform`, but also going through the transforms of all parents. Parents
+    // that have been despawned are ignored.
+    pub fn world_transform(&self, screen: (f32, f32)) -> Transform {
+        let local = self.matrix.transform(screen, self.image_size());
+        match self.parent_transform(screen) {
+            Some(parent) => parent * local,
+            None => local,
+        }
+    }
+
+    // Size of the part of the image that's drawn, in pixels
+    pub fn image_size(&self) -> (f32, f32) {
+        match &self.matrix.source {
+            Some(source) => (source.width, source.height),
+            None => (self.dimensions.0 as f32, self.dimensions.1 as f32),
+        }
+    }
+
+    pub fn pixel_size(&self, screen: (f32, f32)) -> (f32, f32) {
+        self.matrix.pixel_size(screen, self.image_size())
+    }
+
+    // Lowest point of the bottom edge on the screen, in pixels and after all transforms. What
+    // `SortMode::YSort` sorts by.
+    pub fn bottom_edge(&self, screen: (f32, f32)) -> f32 {
+        let size = self.pixel_size(screen);
+        let t = self.world_transform(screen);
+        t.apply((0.0, size.1)).1.max(t.apply(size).1)
+    }
+
+    fn parent_transform(&self, screen: (f32, f32)) -> Option<Transform> {
+        self.parent
+            .as_ref()
+            .and_then(Weak::upgrade)
+            .map(|parent| parent.lock().unwrap().world_transform(screen))
+    }
+
+    // Same as `to_vert`, but only rebuilt when the matrix, dimensions, uvs, screen or parents
+    // changed
+    pub fn cached_vert(&mut self, screen: (u32, u32)) -> &VertexCache {
+        let parent = self.parent_transform((screen.0 as f32, screen.1 as f32));
+        let unchanged = match &mut self.vertex_cache {
+            Some(c) => {
+                c.matrix == self.matrix
+                    && c.dimensions == self.dimensions
+                    && c.uv_rect == self.uv_rect
+                    && c.screen == screen
+                    && c.parent == parent
+            }
+            None => false,
+        };
+
+        if unchanged {
+            let cache = self.vertex_cache.as_mut().unwrap();
+            cache.unchanged_frames = cache.unchanged_frames.saturating_add(1);
+        } else {
+            self.vertex_cache = Some(VertexCache {
+                matrix: self.matrix.clone(),
+                dimensions: self.dimensions,
+                uv_rect: self.uv_rect,
+                screen: screen,
+                parent: parent,
+                quads: match self.text {
+                    Some(_) => self.glyph_verts(screen),
+                    None => vec![self.to_vert(screen)],
+                },
+                revision: NEXT_REVISION.fetch_add(1, Ordering::Relaxed),
+                unchanged_frames: 0,
+            });
+        }
+        self.vertex_cache.as_ref().unwrap()
+    }
+
+    // Texture coordinates of `Matrix::source` within the loaded image, as (u0, v0, u1, v1)
+    pub fn source_uv(&self) -> [f32; 4] {
+        let uv = self.uv_rect;
+        let source = match &self.matrix.source {
+            Some(source) => source,
+            None => return uv,
+        };
+        let dims = (self.dimensions.0 as f32, self.dimensions.1 as f32);
+        let u = |x: f32| uv[0] + (uv[2] - uv[0]) * x / dims.0;
+        let v = |y: f32| uv[1] + (uv[3] - uv[1]) * y / dims.1;
+        [
+            u(source.x),
+            v(source.y),
+            u(source.x + source.width),
+            v(source.y + source.height),
+        ]
+    }
+
     // Impl this for Texture instead so i can use the dimensions field
     pub fn to_vert(self: &Self, screen: (u32, u32)) -> [Vertex; 4] {
-        let scale_x = screen.0 as f32 / self.dimensions.0 as f32;
-        let scale_y = screen.1 as f32 / self.dimensions.1 as f32;
-        let scale = (scale_x, scale_y);
-        [
-            Vertex {
-                // Top-Left
-                position: [self.matrix.pos.0, self.matrix.pos.1],
-                scale: scale,
-            },
-            Vertex {
-                // Bottom-Left
-                position: [self.matrix.pos.0, self.matrix.pos.1 + self.matrix.size.1],
-                scale: scale,
-            },
+        let screen = (screen.0 as f32, screen.1 as f32);
+        let transform = self.world_transform(screen);
+        let size = self.pixel_size(screen);
+        let uv = self.source_uv();
+        // Premultiplied, like the images
+        let c = self.matrix.color;
+        let color = [c[0] * c[3], c[1] * c[3], c[2] * c[3], c[3]];
+        let corner = |x: f32, y: f32| {
+            let p = transform.apply((x * size.0, y * size.1));
             Vertex {
-                // Top-Right
-                position: [self.matrix.pos.0 + self.matrix.size.0, self.matrix.pos.1],
-                scale: scale,
-            },
-            Vertex {
-                // Bottom-Right
-                position: [
-                    self.matrix.pos.0 + self.matrix.size.0,
-                    self.matrix.pos.1 + self.matrix.size.1,
-                ],
-                scale: scale,
-            },
+                position: [p.0 / screen.0, p.1 / screen.1],
+                uv: [uv[0] + (uv[2] - uv[0]) * x, uv[1] + (uv[3] - uv[1]) * y],
+                color: color,
+            }
+        };
+        [
+            corner(0.0, 0.0), // Top-Left
+            corner(0.0, 1.0), // Bottom-Left
+            corner(1.0, 0.0), // Top-Right
+            corner(1.0, 1.0), // Bottom-Right
         ]
     }
+
+    // Like `to_vert` for every glyph, with the laid out block stretched over the texture
+    pub fn glyph_verts(&self, screen: (u32, u32)) -> Vec<[Vertex; 4]> {
+        let text = match &self.text {
+            Some(text) => text,
+            None => return Vec::new(),
+        };
+        if self.dimensions.0 == 0 || self.dimensions.1 == 0 {
+            return Vec::new();
+        }
+        let screen = (screen.0 as f32, screen.1 as f32);
+        let transform = self.world_transform(screen);
+        let size = self.pixel_size(screen);
+        let scale = (
+            size.0 / self.dimensions.0 as f32,
+            size.1 / self.dimensions.1 as f32,
+        );
+        let (t, m) = (text.color, self.matrix.color);
+        let a = t[3] * m[3];
+        let color = [t[0] * m[0] * a, t[1] * m[1] * a, t[2] * m[2] * a, a];
+        let corner = |x: f32, y: f32, u: f32, v: f32| {
+            let p = transform.apply((x * scale.0, y * scale.1));
+            Vertex {
+                position: [p.0 / screen.0, p.1 / screen.1],
+                uv: [u, v],
+                color: color,
+            }
+        };
+        self.glyphs
+            .iter()
+            .map(|g| {
+                let (r, uv) = (g.rect, g.uv);
+                // Same corner order as `to_vert`
+                [
+                    corner(r[0], r[1], uv[0], uv[1]),
+                    corner(r[0], r[3], uv[0], uv[3]),
+                    corner(r[2], r[1], uv[2], uv[1]),
+                    corner(r[2], r[3], uv[2], uv[3]),
+                ]
+            })
+            .collect()
+    }
 }
 
-type TextureLoadAwait = vulkano::command_buffer::CommandBufferExecFuture<
+pub(crate) type TextureLoadAwait = vulkano::command_buffer::CommandBufferExecFuture<
     vulkano::sync::NowFuture,
     vulkano::command_buffer::AutoCommandBuffer,
 >;
 
 impl Texture {
+    // Images are kept with their colors multiplied by their alpha, which every blend mode relies
+    // on. Images that already are don't get multiplied twice, see `TextureOptions::premultiplied`.
+    pub fn decode(&self) -> RgbaImage {
+        let mut img = image::load_from_memory(&self.unloaded)
+            .unwrap_or_else(|e| panic!("Unable to load image {}", e))
+            .to_rgba();
+        if !self.options.premultiplied {
+            color::premultiply(&mut img);
+        }
+        img
+    }
+
     pub fn load_gpu(
         &mut self,
         queue: Arc<device::Queue>,
-        device: Arc<device::Device>,
+        sampler: Arc<Sampler>,
+        pipeline: Arc<DrawGraphicsPipeline>,
+    ) {
+        let img = self.decode();
+        self.load_image(img, queue, sampler, pipeline);
+    }
+
+    // Gives the texture an image of its own, for images that are already decoded
+    pub fn load_image(
+        &mut self,
+        img: RgbaImage,
+        queue: Arc<device::Queue>,
+        sampler: Arc<Sampler>,
         pipeline: Arc<DrawGraphicsPipeline>,
     ) {
-        let img = image::load_from_memory(&self.unloaded)
-            .unwrap_or_else(|e| panic!("Unable to load image {}", e));
-        let dims = img.dimensions();
-        self.dimensions = dims;
-
-        let (tex, fut) = ImmutableImage::from_iter(
-            img.to_rgba().into_raw().iter().cloned(),
-            Dimensions::Dim2d {
-                width: dims.0,
-                height: dims.1,
-            },
+        self.dimensions = img.dimensions();
+        self.uv_rect = [0.0, 0.0, 1.0, 1.0];
+
+        let (set, fut) = upload_image(
+            img,
+            self.options.mipmaps,
             Format::R8G8B8A8Srgb,
             queue,
+            sampler,
+            pipeline,
+        );
+        self.loaded = Some(set);
+        self.waiter = Some(fut)
+    }
+}
+
+// Uploads an image and creates a descriptor set sampling it. The image can't be used until the
+// returned future has been waited on.
+// `format` is `R8G8B8A8Srgb` for images, mip levels are averaged assuming that
+pub(crate) fn upload_image(
+    img: RgbaImage,
+    mipmaps: bool,
+    format: Format,
+    queue: Arc<device::Queue>,
+    sampler: Arc<Sampler>,
+    pipeline: Arc<DrawGraphicsPipeline>,
+) -> (Arc<DescriptorSet + Send + Sync>, TextureLoadAwait) {
+    let (tex, fut) = match mipmaps {
+        true => upload_mip_chain(img, format, queue),
+        false => {
+            let dims = img.dimensions();
+            ImmutableImage::from_iter(
+                img.into_raw().into_iter(),
+                Dimensions::Dim2d {
+                    width: dims.0,
+                    height: dims.1,
+                },
+                format,
+                queue,
+            )
+            .unwrap()
+        }
+    };
+
+    let set = Arc::new(
+        PersistentDescriptorSet::start(pipeline, 0)
+            .add_sampled_image(tex, sampler)
+            .unwrap()
+            .build()
+            .unwrap(),
+    );
+    (set, fut)
+}
+
+// Mip levels are generated on the CPU and copied into the image one by one
+fn upload_mip_chain(
+    img: RgbaImage,
+    format: Format,
+    queue: Arc<device::Queue>,
+) -> (Arc<ImmutableImage<Format>>, TextureLoadAwait) {
+    let device = queue.device().clone();
+    let levels = mipmap::mip_chain(img);
+    let (width, height) = levels[0].dimensions();
+
+    let (tex, init) = ImmutableImage::uninitialized(
+        device.clone(),
+        Dimensions::Dim2d {
+            width: width,
+            height: height,
+        },
+        format,
+        MipmapsCount::Specific(levels.len() as u32),
+        ImageUsage {
+            transfer_destination: true,
+            sampled: true,
+            ..ImageUsage::none()
+        },
+        ImageLayout::ShaderReadOnlyOptimal,
+        Some(queue.family()),
+    )
+    .unwrap();
+    let init = Arc::new(init);
+
+    let mut cb = AutoCommandBufferBuilder::new(device.clone(), queue.family()).unwrap();
+    for (level, img) in levels.into_iter().enumerate() {
+        let (w, h) = img.dimensions();
+        let buffer = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::transfer_source(),
+            img.into_raw().into_iter(),
         )
         .unwrap();
+        cb = cb
+            .copy_buffer_to_image_dimensions(
+                buffer,
+                init.clone(),
+                [0, 0, 0],
+                [w, h, 1],
+                0,
+                1,
+                level as u32,
+            )
+            .unwrap();
+    }
+    let fut = cb.build().unwrap().execute(queue).unwrap();
+    (tex, fut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-3 && (a.1 - b.1).abs() < 1e-3
+    }
+
+    const SCREEN: (f32, f32) = (800.0, 600.0);
+
+    // 40x20 pixels with its top-left corner at (10, 20)
+    fn matrix() -> Matrix {
+        Matrix::pixels((10.0, 20.0)).with_size((40.0, 20.0))
+    }
 
-        let sampler = draw::default_sampler(device.clone());
+    fn corners(m: &Matrix) -> Vec<(f32, f32)> {
+        let t = m.transform(SCREEN, (0.0, 0.0));
+        [(0.0, 0.0), (40.0, 0.0), (0.0, 20.0), (40.0, 20.0)]
+            .iter()
+            .map(|&p| t.apply(p))
+            .collect()
+    }
 
-        let set = Arc::new(
-            PersistentDescriptorSet::start(pipeline, 0)
-                .add_sampled_image(tex, sampler)
-                .unwrap()
-                .build()
-                .unwrap(),
+    fn assert_corners(m: &Matrix, expected: [(f32, f32); 4]) {
+        let actual = corners(m);
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!(close(*a, *e), "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn defaults_only_move_the_texture() {
+        assert_corners(
+            &matrix(),
+            [(10.0, 20.0), (50.0, 20.0), (10.0, 40.0), (50.0, 40.0)],
         );
-        self.loaded = Some(set);
-        self.waiter = Some(fut)
+    }
+
+    #[test]
+    fn normalized_units_are_fractions_of_the_screen() {
+        let m = Matrix::new((0.5, 0.5), (0.05, 0.1));
+        let t = m.transform(SCREEN, (0.0, 0.0));
+        assert!(close(t.apply((0.0, 0.0)), (400.0, 300.0)));
+        assert!(close(t.apply((40.0, 60.0)), (440.0, 360.0)));
+    }
+
+    #[test]
+    fn scales_around_the_pivot() {
+        let mut m = matrix();
+        m.scale = (2.0, 3.0);
+        m.pivot = (0.0, 0.0);
+        assert_corners(&m, [(10.0, 20.0), (90.0, 20.0), (10.0, 80.0), (90.0, 80.0)]);
+
+        // The bottom-right corner stays put
+        m.pivot = (1.0, 1.0);
+        assert_corners(
+            &m,
+            [(-30.0, -20.0), (50.0, -20.0), (-30.0, 40.0), (50.0, 40.0)],
+        );
+    }
+
+    #[test]
+    fn flips_in_place() {
+        let mut m = matrix();
+        m.flip = (true, false);
+        assert_corners(&m, [(50.0, 20.0), (10.0, 20.0), (50.0, 40.0), (10.0, 40.0)]);
+
+        // Still in place with a pivot that isn't the center
+        m.flip = (false, true);
+        m.pivot = (0.0, 0.0);
+        assert_corners(&m, [(10.0, 40.0), (50.0, 40.0), (10.0, 20.0), (50.0, 20.0)]);
+    }
+
+    #[test]
+    fn rotates_clockwise_around_the_pivot() {
+        let mut m = matrix();
+        m.rotation = FRAC_PI_2;
+        m.pivot = (0.0, 0.0);
+        assert_corners(
+            &m,
+            [(10.0, 20.0), (10.0, 60.0), (-10.0, 20.0), (-10.0, 60.0)],
+        );
+
+        // Around the center, which stays where it was
+        m.pivot = (0.5, 0.5);
+        let t = m.transform(SCREEN, (0.0, 0.0));
+        assert!(close(t.apply((20.0, 10.0)), (30.0, 30.0)));
+        assert!(close(t.apply((0.0, 0.0)), (40.0, 10.0)));
+    }
+
+    #[test]
+    fn image_size_ignores_size() {
+        let m = Matrix::pixels((10.0, 20.0));
+        let t = m.transform(SCREEN, (64.0, 32.0));
+        assert!(close(t.apply((64.0, 32.0)), (74.0, 52.0)));
+        assert_eq!(
+            m.with_size((5.0, 5.0)).pixel_size(SCREEN, (64.0, 32.0)),
+            (5.0, 5.0)
+        );
+    }
+
+    #[test]
+    fn bottom_edge_is_transformed() {
+        let mut t = Texture::new(Vec::new(), matrix(), TextureOptions::default());
+        assert!((t.bottom_edge(SCREEN) - 40.0).abs() < 1e-3);
+
+        // Twice the size around the center, so the bottom moves down by half the height
+        t.matrix.scale = (2.0, 2.0);
+        assert!((t.bottom_edge(SCREEN) - 50.0).abs() < 1e-3);
+
+        // Lying on its side, the long edge is now vertical
+        t.matrix.scale = (1.0, 1.0);
+        t.matrix.rotation = FRAC_PI_2;
+        assert!((t.bottom_edge(SCREEN) - 50.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn bottom_edge_of_a_child_includes_its_parent() {
+        let parent = Matrix::pixels((100.0, 200.0)).with_size((10.0, 10.0));
+        let parent = Arc::new(Mutex::new(Texture::new(
+            Vec::new(),
+            parent,
+            TextureOptions::default(),
+        )));
+        let mut child = Texture::new(Vec::new(), matrix(), TextureOptions::default());
+        child.parent = Some(Arc::downgrade(&parent));
+        assert!((child.bottom_edge(SCREEN) - 240.0).abs() < 1e-3);
+
+        parent.lock().unwrap().matrix.scale = (2.0, 2.0);
+        // Scaled around the parent's center at (105, 205), which the child's bottom was 35 below
+        assert!((child.bottom_edge(SCREEN) - 275.0).abs() < 1e-3);
     }
 }
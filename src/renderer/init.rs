@@ -1,12 +1,15 @@
 use std::sync::Arc;
 
-use crate::renderer::vertex::Vertex;
-use crate::renderer::{shader, DrawGraphicsPipeline};
+use crate::renderer::entity::BlendMode;
+use crate::renderer::vertex::{ShapeVertex, Vertex};
+use crate::renderer::{shader, DrawGraphicsPipeline, ShapeGraphicsPipeline};
+use hashbrown::HashMap;
 use vulkano::device;
 use vulkano::device::{Device, DeviceExtensions};
 use vulkano::framebuffer;
 use vulkano::instance;
 use vulkano::pipeline;
+use vulkano::pipeline::blend::{AttachmentBlend, BlendFactor, BlendOp};
 use vulkano::swapchain;
 use vulkano::swapchain::Surface;
 use vulkano_win::VkSurfaceBuild;
@@ -129,6 +132,7 @@ pub fn render_pass(
 pub fn graphics_pipeline(
     device: Arc<device::Device>,
     render_pass: Arc<framebuffer::RenderPassAbstract + Send + Sync>,
+    blend: BlendMode,
 ) -> Arc<DrawGraphicsPipeline> {
     let vs = shader::vs::Shader::load(device.clone()).unwrap();
     let fs = shader::fs::Shader::load(device.clone()).unwrap();
@@ -136,12 +140,79 @@ pub fn graphics_pipeline(
         pipeline::GraphicsPipeline::start()
             .vertex_input_single_buffer::<Vertex>()
             .vertex_shader(vs.main_entry_point(), ())
-            .triangle_strip()
+            .triangle_list()
             .viewports_dynamic_scissors_irrelevant(1)
             .fragment_shader(fs.main_entry_point(), ())
-            .blend_alpha_blending()
+            .blend_collective(attachment_blend(blend))
             .render_pass(framebuffer::Subpass::from(render_pass, 0).unwrap())
             .build(device)
             .unwrap(),
     )
 }
+
+// One pipeline for every blend mode. They all share the same layout, so descriptor sets made for
+// one work with the others.
+pub fn graphics_pipelines(
+    device: Arc<device::Device>,
+    render_pass: Arc<framebuffer::RenderPassAbstract + Send + Sync>,
+) -> HashMap<BlendMode, Arc<DrawGraphicsPipeline>> {
+    [
+        BlendMode::Alpha,
+        BlendMode::Additive,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+    ]
+    .iter()
+    .map(|&blend| {
+        (
+            blend,
+            graphics_pipeline(device.clone(), render_pass.clone(), blend),
+        )
+    })
+    .collect()
+}
+
+// Untextured triangles, see `Shape`
+pub fn shape_pipeline(
+    device: Arc<device::Device>,
+    render_pass: Arc<framebuffer::RenderPassAbstract + Send + Sync>,
+) -> Arc<ShapeGraphicsPipeline> {
+    let vs = shader::shape_vs::Shader::load(device.clone()).unwrap();
+    let fs = shader::shape_fs::Shader::load(device.clone()).unwrap();
+    Arc::new(
+        pipeline::GraphicsPipeline::start()
+            .vertex_input_single_buffer::<ShapeVertex>()
+            .vertex_shader(vs.main_entry_point(), ())
+            .triangle_list()
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(fs.main_entry_point(), ())
+            .blend_collective(attachment_blend(BlendMode::Alpha))
+            .render_pass(framebuffer::Subpass::from(render_pass, 0).unwrap())
+            .build(device)
+            .unwrap(),
+    )
+}
+
+// The fragment shaders output premultiplied colors, see `Texture::decode`
+fn attachment_blend(blend: BlendMode) -> AttachmentBlend {
+    let (source, destination) = match blend {
+        BlendMode::Alpha => (BlendFactor::One, BlendFactor::OneMinusSrcAlpha),
+        BlendMode::Additive => (BlendFactor::One, BlendFactor::One),
+        // dst * src + dst * (1 - src alpha), leaving transparent parts untouched
+        BlendMode::Multiply => (BlendFactor::DstColor, BlendFactor::OneMinusSrcAlpha),
+        BlendMode::Screen => (BlendFactor::One, BlendFactor::OneMinusSrcColor),
+    };
+    AttachmentBlend {
+        enabled: true,
+        color_op: BlendOp::Add,
+        color_source: source,
+        color_destination: destination,
+        alpha_op: BlendOp::Add,
+        alpha_source: BlendFactor::One,
+        alpha_destination: BlendFactor::OneMinusSrcAlpha,
+        mask_red: true,
+        mask_green: true,
+        mask_blue: true,
+        mask_alpha: true,
+    }
+}
@@ -0,0 +1,151 @@
+use crate::renderer::entity::Rect;
+use hashbrown::HashSet;
+use winit::{ElementState, Event, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+
+// How many pixels of touchpad scrolling counts as one line of wheel scrolling
+const PIXELS_PER_LINE: f64 = 20.0;
+
+// Keyboard and mouse state, fed from the window event loop once per frame.
+//
+// "pressed"/"released" and scroll are collected between two user updates, and reset by
+// `begin_tick` once the update loop has taken them.
+#[derive(Default, Clone)]
+pub struct Input {
+    keys_down: HashSet<VirtualKeyCode>,
+    keys_pressed: HashSet<VirtualKeyCode>,
+    keys_released: HashSet<VirtualKeyCode>,
+    buttons_down: HashSet<MouseButton>,
+    buttons_pressed: HashSet<MouseButton>,
+    buttons_released: HashSet<MouseButton>,
+    mouse_pos: (f32, f32),
+    scroll: (f32, f32),
+    close_requested: bool,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Input::default()
+    }
+
+    // Is the key currently held down
+    pub fn key_down(&self, key: VirtualKeyCode) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    // Was the key pressed since the last update
+    pub fn key_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    // Was the key released since the last update
+    pub fn key_released(&self, key: VirtualKeyCode) -> bool {
+        self.keys_released.contains(&key)
+    }
+
+    pub fn mouse_down(&self, button: MouseButton) -> bool {
+        self.buttons_down.contains(&button)
+    }
+
+    pub fn mouse_pressed(&self, button: MouseButton) -> bool {
+        self.buttons_pressed.contains(&button)
+    }
+
+    pub fn mouse_released(&self, button: MouseButton) -> bool {
+        self.buttons_released.contains(&button)
+    }
+
+    // Cursor position in the same 0..1 space as `Matrix`
+    pub fn mouse_pos(&self) -> (f32, f32) {
+        self.mouse_pos
+    }
+
+    // Scrolled lines (x, y) since the last update
+    pub fn scroll(&self) -> (f32, f32) {
+        self.scroll
+    }
+
+    pub fn close_requested(&self) -> bool {
+        self.close_requested
+    }
+
+    // `area` is the part of the window (in logical pixels) that the cursor is normalized to, see
+    // `ScreenLayout`.
+    pub(crate) fn handle(&mut self, event: &Event, area: Rect) {
+        let event = match event {
+            Event::WindowEvent { event, .. } => event,
+            _ => return,
+        };
+        match event {
+            WindowEvent::CloseRequested => self.close_requested = true,
+            WindowEvent::KeyboardInput { input, .. } => {
+                let key = match input.virtual_keycode {
+                    Some(key) => key,
+                    None => return,
+                };
+                match input.state {
+                    ElementState::Pressed => {
+                        // Held keys repeat their Pressed event, only the first one counts.
+                        if self.keys_down.insert(key) {
+                            self.keys_pressed.insert(key);
+                        }
+                    }
+                    ElementState::Released => {
+                        self.keys_down.remove(&key);
+                        self.keys_released.insert(key);
+                    }
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => {
+                    self.buttons_down.insert(*button);
+                    self.buttons_pressed.insert(*button);
+                }
+                ElementState::Released => {
+                    self.buttons_down.remove(button);
+                    self.buttons_released.insert(*button);
+                }
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                if area.width > 0.0 && area.height > 0.0 {
+                    self.mouse_pos = (
+                        (position.x as f32 - area.x) / area.width,
+                        (position.y as f32 - area.y) / area.height,
+                    );
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => match delta {
+                MouseScrollDelta::LineDelta(x, y) => {
+                    self.scroll.0 += x;
+                    self.scroll.1 += y;
+                }
+                MouseScrollDelta::PixelDelta(p) => {
+                    self.scroll.0 += (p.x / PIXELS_PER_LINE) as f32;
+                    self.scroll.1 += (p.y / PIXELS_PER_LINE) as f32;
+                }
+            },
+            WindowEvent::Focused(false) => {
+                // We won't get the Released events for keys let go while unfocused
+                self.keys_down.clear();
+                self.buttons_down.clear();
+            }
+            _ => {}
+        }
+    }
+
+    // What the entities get to see during a tick. Presses and releases from then on count
+    // towards the next one.
+    pub(crate) fn begin_tick(&mut self) -> Input {
+        let input = self.clone();
+        self.end_tick();
+        input
+    }
+
+    // Forgets everything that only lasts for a single update.
+    fn end_tick(&mut self) {
+        self.keys_pressed.clear();
+        self.keys_released.clear();
+        self.buttons_pressed.clear();
+        self.buttons_released.clear();
+        self.scroll = (0.0, 0.0);
+    }
+}
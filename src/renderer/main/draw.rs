@@ -1,16 +1,125 @@
-use crate::renderer::entity::Texture;
-use crate::renderer::vertex::Vertex;
+use crate::renderer::entity::{BlendMode, Texture};
+use crate::renderer::options::{self, SamplerOptions};
+use crate::renderer::resolution::ScreenLayout;
+use crate::renderer::shader::{shape_vs, vs};
+use crate::renderer::shape::Shape;
+use crate::renderer::transform::Transform;
+use crate::renderer::vertex::{ShapeVertex, Vertex};
 use crate::renderer::VkSession;
+use std::cmp::Ordering;
+use std::ops::Range;
 use std::sync::{Arc, Mutex};
-use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::buffer::{BufferSlice, BufferUsage, ImmutableBuffer};
 use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor::DescriptorSet;
 use vulkano::device;
+use vulkano::pipeline::viewport::Viewport;
 use vulkano::sampler::{BorderColor, Filter, MipmapMode, Sampler, SamplerAddressMode};
 use vulkano::swapchain;
 use vulkano::sync::{now, GpuFuture};
 
 pub type DrawBuffer = Vec<Arc<Mutex<Texture>>>;
 
+// Corners from `Texture::to_vert` making up the two triangles of a quad. Sprites aren't
+// instanced, every quad is expanded into six vertices since its corners already carry the full
+// transform.
+const QUAD_TRIANGLES: [usize; 6] = [0, 1, 2, 2, 1, 3];
+
+// Consecutive textures that can be drawn with a single call
+struct Batch {
+    set: Arc<DescriptorSet + Send + Sync>,
+    blend: BlendMode,
+    screen_space: bool,
+    // Static batches index into `VkSession::static_revisions` and `static_sources`, dynamic ones
+    // into `vertices`
+    is_static: bool,
+    range: Range<usize>,
+}
+
+// Adds the batch's vertices to the last draw if they can be drawn together. Its range has to
+// start where the last draw's ends.
+fn push_batch(draws: &mut Vec<Draw>, batch: Batch) {
+    match draws.last_mut() {
+        Some(Draw::Sprites(b))
+            if b.is_static == batch.is_static
+                && b.blend == batch.blend
+                && b.screen_space == batch.screen_space
+                && Arc::ptr_eq(&b.set, &batch.set) =>
+        {
+            b.range.end = batch.range.end;
+        }
+        _ => draws.push(Draw::Sprites(batch)),
+    }
+}
+
+// Batches of textures and shapes, in the order they're drawn
+enum Draw {
+    Sprites(Batch),
+    // Indexes into `VkSession::shape_vertices`
+    Shapes {
+        screen_space: bool,
+        range: Range<usize>,
+    },
+}
+
+fn push_quads(vertices: &mut Vec<Vertex>, quads: &[[Vertex; 4]]) {
+    for quad in quads {
+        vertices.extend(QUAD_TRIANGLES.iter().map(|&i| quad[i].clone()));
+    }
+}
+
+// Adds the shape's vertices to the last draw if that's shapes as well
+fn push_shape(draws: &mut Vec<Draw>, vertices: &mut Vec<ShapeVertex>, shape: &Shape) {
+    if shape.vertices.is_empty() {
+        return;
+    }
+    let start = vertices.len();
+    vertices.extend(shape.vertices.iter().cloned());
+    let end = vertices.len();
+    match draws.last_mut() {
+        Some(Draw::Shapes {
+            screen_space,
+            range,
+        }) if *screen_space == shape.screen_space => range.end = end,
+        _ => draws.push(Draw::Shapes {
+            screen_space: shape.screen_space,
+            range: start..end,
+        }),
+    }
+}
+
+// How textures within the same layer are ordered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortMode {
+    // Textures connected later are drawn on top
+    Layer,
+    // Textures whose bottom edge is further down are drawn on top, for top-down games. That's
+    // where the edge ends up on the screen, after scaling, rotating and attaching. Ties are drawn
+    // in the order they were connected.
+    YSort,
+}
+
+// Textures come out of a `HashMap`, so every key ends in the connection order to keep the draw
+// order the same between frames
+fn sort_draw_buffer(draw_buffer: &mut DrawBuffer, mode: SortMode, screen: (f32, f32)) {
+    let mut keyed = draw_buffer
+        .drain(..)
+        .map(|t| {
+            let key = {
+                let t = t.lock().unwrap();
+                let m = &t.matrix;
+                match mode {
+                    SortMode::Layer => (m.layer, 0.0, t.order),
+                    SortMode::YSort => (m.layer, t.bottom_edge(screen), t.order),
+                }
+            };
+            (key, t)
+        })
+        .collect::<Vec<_>>();
+    keyed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+    draw_buffer.extend(keyed.into_iter().map(|(_, t)| t));
+}
+
 impl VkSession {
     // Prints draw buffer to swapchain
     // Needs take mut self to update swapchain if required.
@@ -29,6 +138,7 @@ impl VkSession {
                 Ok(out) => out,
             };
         prev_frame.cleanup_finished();
+
         //prev_frame = Box::new(now(self.device.clone()));
 
         let mut command_buffer = AutoCommandBufferBuilder::primary_one_time_submit(
@@ -44,39 +154,213 @@ impl VkSession {
         .unwrap();
 
         let mut prev_frame = Box::new(prev_frame.join(gpu_fut)) as Box<GpuFuture + Sync + Send>;
-        for i in 0..draw_buffer.len() {
-            let mut draw_set = draw_buffer[i].lock().unwrap();
+
+        let window = self.physical_window_size();
+        let layout = ScreenLayout::new(self.resolution, (window.0 as f32, window.1 as f32));
+        let screen = (layout.units.0 as u32, layout.units.1 as u32);
+        sort_draw_buffer(draw_buffer, self.sort_mode, layout.units);
+        let viewport = layout.viewport;
+        self.render_target.dynamic_state.viewports = Some(vec![Viewport {
+            origin: [viewport.x, viewport.y],
+            dimensions: [viewport.width, viewport.height],
+            depth_range: 0.0..1.0,
+        }]);
+
+        // Dynamic textures all go into the same pooled vertex buffer, static ones get device local
+        // buffers per batch that are kept for as long as the batch stays the same. Consecutive
+        // textures sharing a descriptor set and blend mode are drawn with a single call.
+        // Buffers are kept per batch rather than per texture so a static scene stays at one draw
+        // per batch, at the cost of re-uploading the whole batch when one of its textures changes.
+        // Static textures only have their vertices copied when their batch is uploaded.
+        // Shapes are drawn on top of the textures in their layer.
+        self.frame += 1;
+        self.vertices.clear();
+        self.static_revisions.clear();
+        self.static_sources.clear();
+        self.shape_vertices.clear();
+        let mut draws: Vec<Draw> = Vec::new();
+        let mut next_shape = 0;
+        for (index, t) in draw_buffer.iter().enumerate() {
+            let mut draw_set = t.lock().unwrap();
+            while next_shape < self.shapes.len()
+                && self.shapes[next_shape].layer < draw_set.matrix.layer
+            {
+                push_shape(&mut draws, &mut self.shape_vertices, &self.shapes[next_shape]);
+                next_shape += 1;
+            }
+            // Text is drawn from the glyph cache, which gets a new descriptor set whenever it grows
+            let loaded = match draw_set.text {
+                Some(_) => self.glyph_set.clone(),
+                None => draw_set.loaded.clone(),
+            };
+            // Not uploaded to the GPU yet
+            let set = match loaded {
+                Some(set) => set,
+                None => continue,
+            };
             if draw_set.waiter.is_some() {
                 prev_frame = Box::new(prev_frame.join(Box::new(draw_set.waiter.take().unwrap())));
             }
 
-            let window = self.render_target.surface.window();
+            let blend = draw_set.matrix.blend;
+            let screen_space = draw_set.matrix.screen_space;
+            let cache = draw_set.cached_vert(screen);
+            // Text without any visible glyphs
+            if cache.quads.is_empty() {
+                continue;
+            }
+            let is_static = cache.is_static();
+            let range = match is_static {
+                true => {
+                    self.static_revisions.push(cache.revision);
+                    self.static_sources.push(index);
+                    let end = self.static_revisions.len();
+                    end - 1..end
+                }
+                false => {
+                    let start = self.vertices.len();
+                    push_quads(&mut self.vertices, &cache.quads);
+                    start..self.vertices.len()
+                }
+            };
+            push_batch(
+                &mut draws,
+                Batch {
+                    set: set,
+                    blend: blend,
+                    screen_space: screen_space,
+                    is_static: is_static,
+                    range: range,
+                },
+            );
+        }
+        for shape in &self.shapes[next_shape..] {
+            push_shape(&mut draws, &mut self.shape_vertices, shape);
+        }
+
+        let dynamic_buffer = match self.vertices.is_empty() {
+            true => None,
+            false => Some(self.vertex_pools.upload(&self.vertices)),
+        };
+        let shape_buffer = match self.shape_vertices.is_empty() {
+            true => None,
+            false => Some(self.shape_pools.upload(&self.shape_vertices)),
+        };
 
-            command_buffer = command_buffer
-                .draw(
-                    self.draw_pipeline.clone(),
-                    &self.render_target.dynamic_state,
-                    CpuAccessibleBuffer::<[Vertex]>::from_iter(
-                        self.device.clone(),
-                        BufferUsage::all(),
-                        draw_set
-                            .to_vert(
-                                window
-                                    .get_inner_size()
-                                    .unwrap()
-                                    .to_physical(window.get_hidpi_factor())
-                                    .into(),
+        // Vertices are in world units, the camera is applied in the vertex shader so moving it
+        // doesn't touch any vertex buffers
+        let pixels = layout.units;
+        let area = layout.area;
+        let to_clip = Transform::translate(-1.0, -1.0)
+            * Transform::scale(2.0 / viewport.width, 2.0 / viewport.height)
+            * Transform::translate(area.x - viewport.x, area.y - viewport.y)
+            * Transform::scale(area.width / pixels.0, area.height / pixels.1);
+        let world_view = vs::ty::PushConstants {
+            view: (to_clip * self.camera.view(pixels)).to_mat4(),
+        };
+        let screen_view = vs::ty::PushConstants {
+            view: (to_clip * Transform::scale(pixels.0, pixels.1)).to_mat4(),
+        };
+        // Shapes are in pixels rather than 0..1
+        let shape_world_view = shape_vs::ty::PushConstants {
+            view: (to_clip
+                * self.camera.view(pixels)
+                * Transform::scale(1.0 / pixels.0, 1.0 / pixels.1))
+            .to_mat4(),
+        };
+        let shape_screen_view = shape_vs::ty::PushConstants {
+            view: to_clip.to_mat4(),
+        };
+
+        for draw in draws {
+            let batch = match draw {
+                Draw::Sprites(batch) => batch,
+                Draw::Shapes {
+                    screen_space,
+                    range,
+                } => {
+                    let view = match screen_space {
+                        true => shape_screen_view,
+                        false => shape_world_view,
+                    };
+                    command_buffer = command_buffer
+                        .draw(
+                            self.shape_pipeline.clone(),
+                            &self.render_target.dynamic_state,
+                            BufferSlice::from_typed_buffer_access(shape_buffer.clone().unwrap())
+                                .slice(range)
+                                .unwrap(),
+                            (),
+                            view,
+                        )
+                        .unwrap();
+                    continue;
+                }
+            };
+            let pipeline = self.draw_pipeline(batch.blend);
+            let view = match batch.screen_space {
+                true => screen_view,
+                false => world_view,
+            };
+            command_buffer = match batch.is_static {
+                true => {
+                    let frame = self.frame;
+                    let revisions = &self.static_revisions[batch.range.clone()];
+                    // No other batch this frame can start with the same texture
+                    let cached = match self.static_batches.get_mut(&revisions[0]) {
+                        Some(c) if c.0[..] == *revisions => {
+                            c.2 = frame;
+                            Some(c.1.clone())
+                        }
+                        _ => None,
+                    };
+                    let buffer = match cached {
+                        Some(buffer) => buffer,
+                        None => {
+                            self.static_vertices.clear();
+                            for &i in &self.static_sources[batch.range] {
+                                let t = draw_buffer[i].lock().unwrap();
+                                let cache = t.vertex_cache.as_ref().unwrap();
+                                push_quads(&mut self.static_vertices, &cache.quads);
+                            }
+                            let (buffer, upload) = ImmutableBuffer::from_iter(
+                                self.static_vertices.iter().cloned(),
+                                BufferUsage::vertex_buffer(),
+                                self.queue.clone(),
                             )
-                            .iter()
-                            .cloned(),
+                            .unwrap();
+                            prev_frame = Box::new(prev_frame.join(upload));
+                            self.static_batches
+                                .insert(revisions[0], (revisions.to_vec(), buffer.clone(), frame));
+                            buffer
+                        }
+                    };
+                    command_buffer.draw(
+                        pipeline,
+                        &self.render_target.dynamic_state,
+                        buffer,
+                        batch.set,
+                        view,
                     )
-                    .unwrap(),
-                    draw_set.loaded.clone().unwrap(),
-                    (),
-                )
-                .unwrap()
+                }
+                false => command_buffer.draw(
+                    pipeline,
+                    &self.render_target.dynamic_state,
+                    BufferSlice::from_typed_buffer_access(dynamic_buffer.clone().unwrap())
+                        .slice(batch.range)
+                        .unwrap(),
+                    batch.set,
+                    view,
+                ),
+            }
+            .unwrap();
         }
 
+        // Batches that weren't drawn this frame have changed, frames still in flight hold on to
+        // their buffers for as long as they need them
+        let frame = self.frame;
+        self.static_batches.retain(|_, c| c.2 == frame);
+
         let cb = command_buffer
             .end_render_pass()
             .map_err(|e| eprintln!("\n\n{:?}\n\n", e))
@@ -105,26 +389,53 @@ impl VkSession {
                 );
             }
         };
+        // Only once the frame went through, a retry above reuses the pools it already wrote to
+        self.vertex_pools.next_frame();
+        self.shape_pools.next_frame();
         Box::new(f) as Box<GpuFuture + Send + Sync>
     }
 }
 
-pub fn default_sampler(device: Arc<device::Device>) -> Arc<Sampler> {
+pub fn create_sampler(device: Arc<device::Device>, options: &SamplerOptions) -> Arc<Sampler> {
+    let filter = |f| match f {
+        options::Filter::Nearest => Filter::Nearest,
+        options::Filter::Linear => Filter::Linear,
+    };
+    let border = match options.border_color {
+        options::BorderColor::TransparentBlack => BorderColor::FloatTransparentBlack,
+        options::BorderColor::OpaqueBlack => BorderColor::FloatOpaqueBlack,
+        options::BorderColor::OpaqueWhite => BorderColor::FloatOpaqueWhite,
+    };
+    let address = |a| match a {
+        options::AddressMode::Repeat => SamplerAddressMode::Repeat,
+        options::AddressMode::MirroredRepeat => SamplerAddressMode::MirroredRepeat,
+        options::AddressMode::ClampToEdge => SamplerAddressMode::ClampToEdge,
+        options::AddressMode::ClampToBorder => SamplerAddressMode::ClampToBorder(border),
+    };
+    let mipmap_mode = match options.mipmap_mode {
+        options::MipmapMode::Nearest => MipmapMode::Nearest,
+        options::MipmapMode::Linear => MipmapMode::Linear,
+    };
+
+    let max_anisotropy = match device.enabled_features().sampler_anisotropy {
+        true => (options.max_anisotropy.max(1) as f32)
+            .min(device.physical_device().limits().max_sampler_anisotropy()),
+        false => 1.0,
+    };
+
     Sampler::new(
         device,
-        Filter::Linear,
-        Filter::Linear,
-        MipmapMode::Nearest,
-        //SamplerAddressMode::ClampToBorder(BorderColor::FloatTransparentBlack),
-        //SamplerAddressMode::ClampToBorder(BorderColor::FloatTransparentBlack),
-        //SamplerAddressMode::ClampToBorder(BorderColor::FloatTransparentBlack),
-        SamplerAddressMode::Repeat,
-        SamplerAddressMode::Repeat,
-        SamplerAddressMode::Repeat,
-        0.0,
-        1.0,
+        filter(options.mag_filter),
+        filter(options.min_filter),
+        mipmap_mode,
+        address(options.address_mode.0),
+        address(options.address_mode.1),
+        address(options.address_mode.0),
         0.0,
+        max_anisotropy,
         0.0,
+        // Never limits the level, images without mipmaps only have the one anyway
+        1000.0,
     )
     .unwrap()
 }
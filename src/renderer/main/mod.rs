@@ -1,17 +1,28 @@
+use crate::renderer::atlas::{AtlasBuilder, AtlasLayout};
+use crate::renderer::entity::{self, BlendMode, Texture};
+use crate::renderer::options::{AddressMode, SamplerOptions, TextureOptions};
+use crate::renderer::resolution::ScreenLayout;
 use crate::renderer::{Game, VkSession};
+use hashbrown::HashMap;
+use rusttype::Font;
+use std::mem;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use vulkano::format::Format;
 use vulkano::sync;
+use vulkano::sync::GpuFuture;
 
 pub(crate) mod draw;
 mod framecounter;
+pub(crate) mod pool;
+mod update;
 use framecounter::FPSCounter;
 
 const TODO_THREAD_COUNT: i32 = 4;
 
 impl VkSession {
-    pub fn vk_main<S>(mut self, mut game: Game<S>) {
+    // Runs until the window is closed or `Game::quit` is called, then returns the user state
+    pub fn vk_main<S: Send + 'static>(mut self, game: Game<S>) -> S {
         // I think I'll do similar design to zircon 1.0, with one user update loop, and one render
         // update loop.
 
@@ -20,47 +31,337 @@ impl VkSession {
 
         let mut draw_buffer = draw::DrawBuffer::new();
 
-        // TODO: Make concurrent
-        for t in game.enabled_textures.values_mut() {
-            t.lock().unwrap().load_gpu(
-                self.queue.clone(),
-                self.device.clone(),
-                self.draw_pipeline.clone(),
-            );
-        }
-        for t in game.disabled_textures.values_mut() {
-            t.lock().unwrap().load_gpu(
-                self.queue.clone(),
-                self.device.clone(),
-                self.draw_pipeline.clone(),
-            );
-        }
-
         let shared_state = Arc::new(Mutex::new(game));
 
         // TODO: Mutation settings can be in Arc<Texture>
 
         let user_state = shared_state.clone();
-        thread::spawn(move || {
-            loop {
-                // TODO: Better timer that takes computation time into consideration
-                thread::sleep(Duration::from_millis(60));
-            }
-        });
+        let user_thread = thread::spawn(move || update::update_loop(user_state));
 
         let mut fps = FPSCounter::new();
 
         let mut prev_frame =
             Box::new(sync::now(self.device.clone())) as Box<sync::GpuFuture + Send + Sync>;
         loop {
-            // Prepare all textures that'll be rendered
             let mut game = shared_state.lock().unwrap();
-            for (_k, t) in game.enabled_textures.drain() {
-                draw_buffer.push(t.clone());
+
+            // Feed window events to the input state. Window events are in logical pixels, while
+            // drawing (and so the camera) works in physical ones.
+            let window_size = self.window_size();
+            let layout = ScreenLayout::new(
+                game.resolution,
+                (window_size.0 as f32, window_size.1 as f32),
+            );
+            self.render_target
+                .event_loop
+                .poll_events(|event| game.input.handle(&event, layout.area));
+
+            if game.input.close_requested() {
+                game.quit();
+            }
+            if game.quit {
+                break;
+            }
+
+            // Pick up entities that were enabled, disabled or despawned since last frame
+            if game.draw_dirty {
+                draw_buffer.clear();
+                draw_buffer.extend(game.enabled_textures.values().cloned());
+                game.draw_dirty = false;
+            }
+            if game.shapes_dirty {
+                // Sorted by label within a layer, so the order doesn't change between frames
+                let mut shapes = game.shapes.iter().collect::<Vec<_>>();
+                shapes.sort_by(|a, b| (a.1.layer, a.0).cmp(&(b.1.layer, b.0)));
+                self.shapes = shapes.into_iter().map(|(_, s)| s.clone()).collect();
+                game.shapes_dirty = false;
             }
+            let fonts = match game.fonts_dirty {
+                true => {
+                    game.fonts_dirty = false;
+                    Some(game.fonts.clone())
+                }
+                false => None,
+            };
+            self.sort_mode = game.sort_mode;
+            let physical = self.physical_window_size();
+            game.camera.screen =
+                ScreenLayout::new(game.resolution, (physical.0 as f32, physical.1 as f32)).units;
+            self.camera = game.camera.clone();
+            self.resolution = game.resolution;
+            game.pool_stats = self.vertex_pools.stats();
+            game.shape_pool_stats = self.shape_pools.stats();
+            let loads = mem::replace(&mut game.pending_loads, Vec::new());
+            let despawned = mem::replace(&mut game.despawned, Vec::new());
+            let atlas = game.atlas.take();
             drop(game);
+
+            // Text is laid out rather than decoded
+            let (texts, loads): (Vec<_>, Vec<_>) = loads
+                .into_iter()
+                .partition(|(_, t)| t.lock().unwrap().text.is_some());
+
+            // TODO: Make concurrent
+            match atlas {
+                Some((page_size, padding)) => {
+                    let (layout, uploads) = self.load_atlas(loads, page_size, padding);
+                    for upload in uploads {
+                        prev_frame = Box::new(prev_frame.join(upload));
+                    }
+                    shared_state.lock().unwrap().atlas_layout = Some(layout);
+                }
+                None => {
+                    for (_, t) in loads {
+                        let mut t = t.lock().unwrap();
+                        let sampler = self.sampler(&t.options.sampler);
+                        t.load_gpu(
+                            self.queue.clone(),
+                            sampler,
+                            self.draw_pipeline(BlendMode::Alpha),
+                        );
+                    }
+                }
+            }
+
+            // Frames still in flight keep their own references to the images and descriptor
+            // sets, so dropping ours frees them once those frames' futures are cleaned up.
+            for t in despawned {
+                let mut t = t.lock().unwrap();
+                // An upload that was never waited on would block when dropped
+                if let Some(waiter) = t.waiter.take() {
+                    prev_frame = Box::new(prev_frame.join(waiter));
+                }
+                t.loaded = None;
+            }
+
+            if fonts.is_some() || !texts.is_empty() {
+                if let Some(upload) = self.layout_texts(fonts, texts) {
+                    prev_frame = Box::new(prev_frame.join(upload));
+                }
+            }
+
             prev_frame = self.present(&mut draw_buffer, prev_frame);
             fps.tick_and_display();
         }
+
+        // Dropping the last frame blocks until its fence is signaled
+        drop(prev_frame);
+        // Nothing else submits to the queues anymore at this point
+        unsafe { self.device.wait() }.unwrap();
+
+        user_thread.join().unwrap();
+
+        let mut game = match Arc::try_unwrap(shared_state) {
+            Ok(game) => game.into_inner().unwrap(),
+            Err(_) => panic!("Game is still shared after the update thread exited"),
+        };
+        for entity in game.entities.values_mut() {
+            entity.teardown();
+        }
+
+        game.user_global_state.unwrap()
+    }
+
+    // Packs the textures' images into shared pages and points every texture at its part of a
+    // page. The pages can't be drawn before the returned uploads are done.
+    fn load_atlas(
+        &mut self,
+        loads: Vec<(String, Arc<Mutex<Texture>>)>,
+        page_size: u32,
+        padding: u32,
+    ) -> (AtlasLayout, Vec<entity::TextureLoadAwait>) {
+        // Everything on a page shares a sampler, so textures with different options are packed
+        // separately. Whether they were premultiplied doesn't matter anymore once decoded.
+        let mut builders = HashMap::new();
+        let mut textures = HashMap::new();
+        for (label, t) in loads {
+            let (img, options) = {
+                let t = t.lock().unwrap();
+                let options = TextureOptions {
+                    premultiplied: false,
+                    ..t.options
+                };
+                (t.decode(), options)
+            };
+            builders
+                .entry(options)
+                .or_insert_with(|| AtlasBuilder::new(page_size, padding))
+                .add(&label, img);
+            textures.insert(label, t);
+        }
+
+        let mut layout = AtlasLayout::new(page_size, padding);
+        let mut uploads = Vec::new();
+        for (options, builder) in builders {
+            let (pages, group, skipped) = builder.build();
+            let sampler = self.sampler(&options.sampler);
+            let pipeline = self.draw_pipeline(BlendMode::Alpha);
+
+            let sets = pages
+                .into_iter()
+                .map(|page| {
+                    let (set, upload) = entity::upload_image(
+                        page,
+                        options.mipmaps,
+                        Format::R8G8B8A8Srgb,
+                        self.queue.clone(),
+                        sampler.clone(),
+                        pipeline.clone(),
+                    );
+                    uploads.push(upload);
+                    set
+                })
+                .collect::<Vec<_>>();
+
+            for entry in &group.entries {
+                let mut t = textures[&entry.label].lock().unwrap();
+                t.dimensions = (entry.width, entry.height);
+                t.uv_rect = group.uv_rect(entry);
+                t.loaded = Some(sets[entry.page].clone());
+            }
+            for (label, img) in skipped {
+                textures[&label].lock().unwrap().load_image(
+                    img,
+                    self.queue.clone(),
+                    sampler.clone(),
+                    pipeline.clone(),
+                );
+            }
+            layout.append(group);
+        }
+
+        (layout, uploads)
+    }
+
+    // Lays out the texts that changed and rasterizes the glyphs they need. Every text is laid out
+    // again when the fonts change or the glyph cache had to be cleared, since their glyphs could
+    // have moved. Returns the upload of the glyph cache if it changed, which has to finish before
+    // the text can be drawn.
+    fn layout_texts(
+        &mut self,
+        fonts: Option<HashMap<String, Arc<Font<'static>>>>,
+        loads: Vec<(String, Arc<Mutex<Texture>>)>,
+    ) -> Option<entity::TextureLoadAwait> {
+        for (label, t) in &loads {
+            self.texts.insert(label.clone(), Arc::downgrade(t));
+        }
+        self.texts.retain(|_, t| t.upgrade().is_some());
+
+        let mut texts = loads;
+        if let Some(fonts) = fonts {
+            // Glyphs are cached by font address, which a replaced font's successor could end up at
+            let replaced = self
+                .fonts
+                .iter()
+                .any(|(name, font)| fonts.get(name).map_or(true, |f| !Arc::ptr_eq(f, font)));
+            if replaced {
+                self.glyph_cache.clear();
+            }
+            self.fonts = fonts;
+            // Also picks up texts that were waiting for a font to be loaded
+            texts = self.live_texts();
+        }
+
+        let mut fits = self.layout_all(&texts);
+        if !fits {
+            self.glyph_cache.clear();
+            texts = self.live_texts();
+            fits = self.layout_all(&texts);
+        }
+        if !fits {
+            eprintln!("Too many glyphs for the glyph cache, not drawing any text");
+            for (_, t) in &texts {
+                let mut t = t.lock().unwrap();
+                t.glyphs.clear();
+                t.vertex_cache = None;
+            }
+        }
+
+        if !self.glyph_cache.dirty {
+            return None;
+        }
+        self.glyph_cache.dirty = false;
+        let sampler = self.sampler(&SamplerOptions {
+            address_mode: (AddressMode::ClampToEdge, AddressMode::ClampToEdge),
+            ..SamplerOptions::default()
+        });
+        // Coverage isn't a color, so it's sampled as is rather than decoded from sRGB
+        let (set, upload) = entity::upload_image(
+            self.glyph_cache.image().clone(),
+            false,
+            Format::R8G8B8A8Unorm,
+            self.queue.clone(),
+            sampler,
+            self.draw_pipeline(BlendMode::Alpha),
+        );
+        self.glyph_set = Some(set);
+        Some(upload)
+    }
+
+    fn live_texts(&self) -> Vec<(String, Arc<Mutex<Texture>>)> {
+        self.texts
+            .iter()
+            .filter_map(|(label, t)| t.upgrade().map(|t| (label.clone(), t)))
+            .collect()
+    }
+
+    // False as soon as one of them doesn't fit in the glyph cache
+    fn layout_all(&mut self, texts: &[(String, Arc<Mutex<Texture>>)]) -> bool {
+        texts
+            .iter()
+            .all(|(label, t)| self.layout_text(label, &mut t.lock().unwrap()))
+    }
+
+    // Places the glyphs of a text texture and sizes it to fit them. False if the glyph cache is
+    // full. Its vertices are only rebuilt if the glyphs changed.
+    fn layout_text(&mut self, label: &str, t: &mut Texture) -> bool {
+        let text = match &t.text {
+            Some(text) => text,
+            None => return true,
+        };
+        let font = match self.fonts.get(&text.font) {
+            Some(font) => font,
+            None => {
+                eprintln!("Not drawing text {}, font {} isn't loaded", label, text.font);
+                if !t.glyphs.is_empty() {
+                    t.glyphs.clear();
+                    t.vertex_cache = None;
+                }
+                return true;
+            }
+        };
+        let layout = text.layout(font);
+        let id = &**font as *const Font as usize;
+        match self.glyph_cache.quads(id, &layout.glyphs) {
+            Some(quads) => {
+                // A change in dimensions is picked up by the vertex cache on its own
+                if quads != t.glyphs {
+                    t.glyphs = quads;
+                    t.vertex_cache = None;
+                }
+                t.dimensions = (layout.size.0.ceil() as u32, layout.size.1.ceil() as u32);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Logical size of the window, in the same units winit reports cursor positions in
+    fn window_size(&self) -> (f64, f64) {
+        self.render_target
+            .surface
+            .window()
+            .get_inner_size()
+            .map(|size| (size.width, size.height))
+            .unwrap_or((0.0, 0.0))
+    }
+
+    // What the swapchain images and viewports are sized in
+    fn physical_window_size(&self) -> (u32, u32) {
+        let window = self.render_target.surface.window();
+        window
+            .get_inner_size()
+            .map(|size| size.to_physical(window.get_hidpi_factor()).into())
+            .unwrap_or((0, 0))
     }
 }
@@ -0,0 +1,69 @@
+use std::sync::Arc;
+use vulkano::buffer::cpu_pool::CpuBufferPoolChunk;
+use vulkano::buffer::CpuBufferPool;
+use vulkano::device;
+use vulkano::memory::pool::StdMemoryPool;
+
+pub type VertexChunk<V> = Arc<CpuBufferPoolChunk<V, Arc<StdMemoryPool>>>;
+
+// Counters for the vertex pools. Once the scene stops growing `allocations` should stay put.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PoolStats {
+    pub frames: u64,
+    pub uploaded_vertices: u64,
+    // Times a pool had to allocate a bigger buffer
+    pub allocations: u64,
+    // Vertices all pools can hold together
+    pub capacity: usize,
+}
+
+// One vertex pool per frame in flight, cycled through every frame.
+//
+// By the time a pool comes around again the frame that used it has finished, so its memory can
+// be reused as is instead of allocating in the hot loop.
+pub struct FramePools<V> {
+    pools: Vec<CpuBufferPool<V>>,
+    current: usize,
+    stats: PoolStats,
+}
+
+impl<V: Clone + Send + Sync + 'static> FramePools<V> {
+    pub fn new(device: Arc<device::Device>, frames_in_flight: usize) -> Self {
+        FramePools {
+            pools: (0..frames_in_flight.max(1))
+                .map(|_| CpuBufferPool::vertex_buffer(device.clone()))
+                .collect(),
+            current: 0,
+            stats: PoolStats::default(),
+        }
+    }
+
+    pub fn next_frame(&mut self) {
+        self.current = (self.current + 1) % self.pools.len();
+        self.stats.frames += 1;
+    }
+
+    // Copies the vertices into the current frame's pool
+    pub fn upload(&mut self, vertices: &[V]) -> VertexChunk<V> {
+        let pool = &self.pools[self.current];
+
+        let before = pool.capacity();
+        if before < vertices.len() {
+            pool.reserve(vertices.len()).unwrap();
+        }
+        let chunk = pool.chunk(vertices.iter().cloned()).unwrap();
+        let after = pool.capacity();
+
+        if after != before {
+            self.stats.allocations += 1;
+            self.stats.capacity = self.stats.capacity - before + after;
+        }
+        self.stats.uploaded_vertices += vertices.len() as u64;
+
+        Arc::new(chunk)
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.stats
+    }
+}
@@ -0,0 +1,209 @@
+use crate::renderer::camera::Camera;
+use crate::renderer::entity::{Command, Entity, EntityContext, Texture};
+use crate::renderer::input::Input;
+use crate::renderer::Game;
+use hashbrown::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How many ticks we're allowed to fall behind before giving up on catching up
+const MAX_TICKS_BEHIND: u32 = 5;
+
+// Runs the entities at a fixed timestep until the game quits.
+//
+// The next tick is scheduled from when the previous one was *due*, not from when it finished,
+// so time spent inside the updates doesn't slow the simulation down.
+//
+// The entities are taken out of the game while they run, so the renderer isn't kept waiting on
+// the game's lock for the whole tick. It only waits on the textures of entities being updated.
+// `Entity::init` still runs with the game locked.
+pub fn update_loop<S>(state: Arc<Mutex<Game<S>>>) {
+    state.lock().unwrap().init_entities();
+
+    let mut next_tick = Instant::now();
+    loop {
+        let mut game = state.lock().unwrap();
+        if game.quit {
+            break;
+        }
+        let tick = Duration::from_secs(1) / game.tick_rate;
+        let dt = 1.0 / game.tick_rate as f32;
+        let mut ticking = game.begin_tick();
+        drop(game);
+
+        let commands = ticking.run(dt);
+        state.lock().unwrap().end_tick(ticking, commands, dt);
+
+        next_tick += tick;
+        let now = Instant::now();
+        if next_tick > now {
+            thread::sleep(next_tick - now);
+        } else if now - next_tick > tick * MAX_TICKS_BEHIND {
+            // Way too slow, drop the backlog instead of spiraling
+            next_tick = now;
+        }
+    }
+}
+
+// Everything entities touch during a tick, taken out of the game until it's over
+struct Ticking<S> {
+    entities: HashMap<String, Box<Entity<S> + Send>>,
+    // Of the enabled entities, in the order they're updated
+    textures: Vec<(String, Arc<Mutex<Texture>>)>,
+    state: S,
+    input: Input,
+    camera: Camera,
+}
+
+impl<S> Ticking<S> {
+    fn run(&mut self, dt: f32) -> Vec<Command<S>> {
+        let mut commands = Vec::new();
+        for (label, texture) in &self.textures {
+            let entity = match self.entities.get_mut(label) {
+                Some(e) => e,
+                None => continue,
+            };
+            let mut texture = texture.lock().unwrap();
+            let mut ctx = EntityContext {
+                matrix: &mut texture.matrix,
+                state: &mut self.state,
+                input: &self.input,
+                camera: &mut self.camera,
+                label: label,
+                commands: &mut commands,
+            };
+            entity.update(&mut ctx, dt);
+        }
+        commands
+    }
+}
+
+impl<S> Game<S> {
+    fn init_entities(&mut self) {
+        let labels = self.entities.keys().cloned().collect::<Vec<_>>();
+        let mut commands = Vec::new();
+        for label in labels {
+            self.init_entity(&label, &mut commands);
+        }
+        self.apply(commands);
+    }
+
+    fn init_entity(&mut self, label: &str, commands: &mut Vec<Command<S>>) {
+        let texture = match (
+            self.enabled_textures.get(label),
+            self.disabled_textures.get(label),
+        ) {
+            (Some(t), _) | (None, Some(t)) => t,
+            (None, None) => return,
+        };
+        let entity = match self.entities.get_mut(label) {
+            Some(e) => e,
+            None => return,
+        };
+        let mut texture = texture.lock().unwrap();
+        let mut ctx = EntityContext {
+            matrix: &mut texture.matrix,
+            state: self.user_global_state.as_mut().unwrap(),
+            input: &self.input,
+            camera: &mut self.camera,
+            label: label,
+            commands: commands,
+        };
+        entity.init(&mut ctx);
+    }
+
+    fn begin_tick(&mut self) -> Ticking<S> {
+        let entities = mem::replace(&mut self.entities, HashMap::new());
+        let textures = entities
+            .keys()
+            .filter_map(|label| {
+                self.enabled_textures
+                    .get(label)
+                    .map(|t| (label.clone(), t.clone()))
+            })
+            .collect();
+        Ticking {
+            entities: entities,
+            textures: textures,
+            state: self.user_global_state.take().unwrap(),
+            input: self.input.begin_tick(),
+            camera: self.camera.clone(),
+        }
+    }
+
+    fn end_tick(&mut self, ticking: Ticking<S>, commands: Vec<Command<S>>, dt: f32) {
+        self.entities = ticking.entities;
+        self.user_global_state = Some(ticking.state);
+        // The renderer may have resized the screen in the meantime
+        let screen = self.camera.screen;
+        self.camera = ticking.camera;
+        self.camera.screen = screen;
+        self.apply(commands);
+        self.follow_camera_target(dt);
+    }
+
+    // Follows the center of the target's texture, wherever its parents put it
+    fn follow_camera_target(&mut self, dt: f32) {
+        let target = match self.camera.following() {
+            Some(label) => match self.texture(label) {
+                Some(t) => t.clone(),
+                None => return,
+            },
+            None => return,
+        };
+        let screen = self.camera.screen;
+        let center = {
+            let t = target.lock().unwrap();
+            let size = t.pixel_size(screen);
+            let p = t.world_transform(screen).apply((size.0 / 2.0, size.1 / 2.0));
+            (p.0 / screen.0, p.1 / screen.1)
+        };
+        self.camera.track(center, dt);
+    }
+
+    // Spawned entities are initialized right away, which may queue up even more commands
+    fn apply(&mut self, mut commands: Vec<Command<S>>) {
+        while !commands.is_empty() {
+            let mut spawned = Vec::new();
+            for command in commands.drain(..) {
+                match command {
+                    Command::Spawn {
+                        label,
+                        matrix,
+                        img,
+                        entity,
+                        enabled,
+                        options,
+                    } => {
+                        self.connect_with(&label, matrix, &img, entity, enabled, options);
+                        spawned.push(label);
+                    }
+                    Command::SpawnText {
+                        label,
+                        matrix,
+                        text,
+                        entity,
+                        enabled,
+                    } => {
+                        self.connect_text(&label, matrix, text, entity, enabled);
+                        spawned.push(label);
+                    }
+                    Command::Despawn(label) => self.despawn(&label),
+                    Command::Attach { child, parent } => self.attach(&child, &parent),
+                    Command::Detach(child) => self.detach(&child),
+                    Command::Enable(label) => self.enable(&label),
+                    Command::Disable(label) => self.disable(&label),
+                    Command::SetShape { label, shape } => self.set_shape(&label, shape),
+                    Command::RemoveShape(label) => self.remove_shape(&label),
+                    Command::SetText { label, text } => self.set_text(&label, text),
+                    Command::Quit => self.quit(),
+                }
+            }
+            for label in spawned {
+                self.init_entity(&label, &mut commands);
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::renderer::color;
+use image::{Rgba, RgbaImage};
+
+// The image followed by versions of it halved in size until 1x1, each pixel averaging the 2x2
+// pixels above it. Odd sizes round down, repeating the last row or column.
+pub fn mip_chain(img: RgbaImage) -> Vec<RgbaImage> {
+    let mut levels = vec![img];
+    loop {
+        let next = {
+            let prev = levels.last().unwrap();
+            let (w, h) = prev.dimensions();
+            if w <= 1 && h <= 1 {
+                break;
+            }
+            halve(prev)
+        };
+        levels.push(next);
+    }
+    levels
+}
+
+// Colors are averaged in linear space, since they're sRGB encoded
+fn halve(src: &RgbaImage) -> RgbaImage {
+    let linear = (0..=255).map(color::to_linear).collect::<Vec<_>>();
+    let (w, h) = src.dimensions();
+    RgbaImage::from_fn((w / 2).max(1), (h / 2).max(1), |x, y| {
+        let xs = [(x * 2).min(w - 1), (x * 2 + 1).min(w - 1)];
+        let ys = [(y * 2).min(h - 1), (y * 2 + 1).min(h - 1)];
+        let mut sum = [0.0; 3];
+        let mut alpha = 0;
+        for &sy in &ys {
+            for &sx in &xs {
+                let p = src.get_pixel(sx, sy);
+                for c in 0..3 {
+                    sum[c] += linear[p[c] as usize];
+                }
+                alpha += p[3] as u32;
+            }
+        }
+        Rgba([
+            color::to_srgb(sum[0] / 4.0),
+            color::to_srgb(sum[1] / 4.0),
+            color::to_srgb(sum[2] / 4.0),
+            ((alpha + 2) / 4) as u8,
+        ])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sizes(w: u32, h: u32) -> Vec<(u32, u32)> {
+        mip_chain(RgbaImage::new(w, h))
+            .iter()
+            .map(|l| l.dimensions())
+            .collect()
+    }
+
+    #[test]
+    fn halves_down_to_one_pixel() {
+        assert_eq!(sizes(8, 8), vec![(8, 8), (4, 4), (2, 2), (1, 1)]);
+        assert_eq!(sizes(1, 1), vec![(1, 1)]);
+    }
+
+    #[test]
+    fn odd_and_uneven_sizes_round_down() {
+        assert_eq!(sizes(5, 3), vec![(5, 3), (2, 1), (1, 1)]);
+        assert_eq!(sizes(8, 2), vec![(8, 2), (4, 1), (2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn averages_in_linear_space() {
+        let img = RgbaImage::from_fn(2, 2, |x, _| match x {
+            0 => Rgba([0, 0, 0, 255]),
+            _ => Rgba([255, 255, 255, 255]),
+        });
+        let levels = mip_chain(img);
+        // Half as bright in linear light, not the 128 of averaging the encoded values
+        assert_eq!(levels[1].get_pixel(0, 0), &Rgba([188, 188, 188, 255]));
+    }
+}
@@ -1,27 +1,82 @@
+pub mod animation;
+pub mod atlas;
+pub mod camera;
+mod color;
 pub(crate) mod entity;
 mod init;
+pub mod input;
 mod main;
+mod mipmap;
+pub mod options;
+pub mod resolution;
 pub mod shader;
+pub mod shape;
+pub mod text;
+pub mod transform;
 pub mod vertex;
 
-use entity::{Entity, Matrix, Texture};
+pub use main::draw::SortMode;
+pub use main::pool::PoolStats;
+
+use atlas::AtlasLayout;
+use camera::Camera;
+use entity::{BlendMode, Entity, Matrix, Texture};
 use hashbrown::HashMap;
-use std::sync::{Arc, Mutex};
+use input::Input;
+use options::{SamplerOptions, TextureOptions};
+use resolution::VirtualResolution;
+use rusttype::Font;
+use shape::Shape;
+use std::sync::{Arc, Mutex, Weak};
+use text::{Text, TextLayout};
+use vulkano::buffer::ImmutableBuffer;
 use vulkano::command_buffer;
+use vulkano::descriptor::DescriptorSet;
 use vulkano::device;
 use vulkano::framebuffer;
 use vulkano::framebuffer::RenderPassAbstract;
 use vulkano::image;
 use vulkano::instance;
 use vulkano::pipeline;
+use vulkano::sampler::Sampler;
 use vulkano::swapchain;
 
 const VSYNC: bool = true;
+const DEFAULT_TICK_RATE: u32 = 60;
 
 pub struct Game<S> {
-    user_global_state: S, // RwLock?
+    // Only taken out while the entities are being updated, see `update_loop`
+    user_global_state: Option<S>,
     enabled_textures: HashMap<String, Arc<Mutex<Texture>>>,
     disabled_textures: HashMap<String, Arc<Mutex<Texture>>>,
+    // Owned by the game so the update thread can mutate them, the render thread only ever
+    // looks at the textures.
+    entities: HashMap<String, Box<Entity<S> + Send>>,
+    input: Input,
+    quit: bool,
+    tick_rate: u32,
+    // Set whenever `enabled_textures` changes, so the renderer knows to rebuild its draw buffer
+    draw_dirty: bool,
+    // Textures the renderer still has to upload, and ones it should let go of
+    pending_loads: Vec<(String, Arc<Mutex<Texture>>)>,
+    despawned: Vec<Arc<Mutex<Texture>>>,
+    sort_mode: SortMode,
+    // Handed out to textures as they're connected, see `Texture::order`
+    next_order: usize,
+    // Copied over from the renderer every frame
+    pool_stats: PoolStats,
+    shape_pool_stats: PoolStats,
+    // Page size and padding, taken by the renderer when it loads the first textures
+    atlas: Option<(u32, u32)>,
+    atlas_layout: Option<AtlasLayout>,
+    camera: Camera,
+    resolution: Option<VirtualResolution>,
+    shapes: HashMap<String, Arc<Shape>>,
+    // Like `draw_dirty`, for `shapes`
+    shapes_dirty: bool,
+    fonts: HashMap<String, Arc<Font<'static>>>,
+    // Like `draw_dirty`, for `fonts`
+    fonts_dirty: bool,
 }
 
 impl<S> Game<S> {
@@ -29,34 +84,281 @@ impl<S> Game<S> {
         Game {
             enabled_textures: HashMap::new(),
             disabled_textures: HashMap::new(),
-            user_global_state: state,
+            entities: HashMap::new(),
+            user_global_state: Some(state),
+            input: Input::new(),
+            quit: false,
+            tick_rate: DEFAULT_TICK_RATE,
+            draw_dirty: true,
+            pending_loads: Vec::new(),
+            despawned: Vec::new(),
+            sort_mode: SortMode::Layer,
+            next_order: 0,
+            pool_stats: PoolStats::default(),
+            shape_pool_stats: PoolStats::default(),
+            atlas: None,
+            atlas_layout: None,
+            camera: Camera::new(),
+            resolution: None,
+            shapes: HashMap::new(),
+            shapes_dirty: false,
+            fonts: HashMap::new(),
+            fonts_dirty: false,
         }
     }
 
+    // How many times per second `Entity::update` runs
+    pub fn set_tick_rate(&mut self, ticks_per_second: u32) {
+        assert!(ticks_per_second > 0, "Tick rate must be at least 1");
+        self.tick_rate = ticks_per_second;
+    }
+
+    // Stops both the render and update loop, making `VkSession::run` return
+    pub fn quit(&mut self) {
+        self.quit = true;
+    }
+
+    pub fn set_sort_mode(&mut self, mode: SortMode) {
+        self.sort_mode = mode;
+    }
+
+    pub fn input(&self) -> &Input {
+        &self.input
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    // Without one, the game is drawn at whatever size the window is
+    pub fn set_virtual_resolution(&mut self, resolution: Option<VirtualResolution>) {
+        self.resolution = resolution;
+    }
+
+    // Sprite vertex buffer pool usage as of the last rendered frame
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pool_stats
+    }
+
+    // Same as `pool_stats`, for the vertices of shapes. Both have to stop allocating for a scene
+    // to be drawn without any allocations.
+    pub fn shape_pool_stats(&self) -> PoolStats {
+        self.shape_pool_stats
+    }
+
+    // Packs the images of everything connected before `VkSession::run` into shared textures of
+    // `page_size` squared pixels, so sprites can be drawn in a single batch. Each image is padded
+    // with `padding` pixels of its own edges to keep filtering from bleeding between neighbours.
+    // Entities spawned later still get a texture of their own.
+    pub fn use_atlas(&mut self, page_size: u32, padding: u32) {
+        self.atlas = Some((page_size, padding));
+    }
+
+    // Where every image ended up, once the renderer has packed the atlas
+    pub fn atlas_layout(&self) -> Option<&AtlasLayout> {
+        self.atlas_layout.as_ref()
+    }
+
+    // Works both before `VkSession::run` and while the game is running (see
+    // `EntityContext::spawn`), the texture is uploaded by the renderer before its next frame.
+    // Connecting to a label that's already taken despawns the previous entity.
     pub fn connect(
         &mut self,
         label: &str,
         matrix: Matrix,
         img: &[u8],
-        entity: Arc<Entity>,
+        entity: Box<Entity<S> + Send>,
         enabled: bool,
     ) {
-        let texture = Texture {
-            unloaded: img.to_vec(),
-            entity: entity,
-            matrix: matrix,
-            dimensions: (500, 500),
-            loaded: None,
-            waiter: None,
-        };
+        self.connect_with(label, matrix, img, entity, enabled, TextureOptions::default());
+    }
+
+    // Same as `connect`, with control over how the texture is sampled
+    pub fn connect_with(
+        &mut self,
+        label: &str,
+        matrix: Matrix,
+        img: &[u8],
+        entity: Box<Entity<S> + Send>,
+        enabled: bool,
+        options: TextureOptions,
+    ) {
+        let texture = Texture::new(img.to_vec(), matrix, options);
+        self.add_texture(label, texture, entity, enabled);
+    }
+
+    // Connects an entity that draws `text` instead of an image, using the same `Matrix` as
+    // textures do. `Matrix::pixels` draws it at the size the text is laid out at. The text is laid
+    // out by the renderer before its next frame, see `set_text` to change it.
+    pub fn connect_text(
+        &mut self,
+        label: &str,
+        matrix: Matrix,
+        text: Text,
+        entity: Box<Entity<S> + Send>,
+        enabled: bool,
+    ) {
+        let mut texture = Texture::new(Vec::new(), matrix, TextureOptions::default());
+        texture.text = Some(text);
+        self.add_texture(label, texture, entity, enabled);
+    }
+
+    fn add_texture(
+        &mut self,
+        label: &str,
+        mut texture: Texture,
+        entity: Box<Entity<S> + Send>,
+        enabled: bool,
+    ) {
+        self.despawn(label);
+
+        texture.order = self.next_order;
+        self.next_order += 1;
+        let texture = Arc::new(Mutex::new(texture));
+        self.pending_loads.push((label.to_owned(), texture.clone()));
         match enabled {
-            true => self
-                .enabled_textures
-                .insert(label.to_owned(), Arc::new(Mutex::new(texture))),
-            false => self
-                .disabled_textures
-                .insert(label.to_owned(), Arc::new(Mutex::new(texture))),
+            true => self.enabled_textures.insert(label.to_owned(), texture),
+            false => self.disabled_textures.insert(label.to_owned(), texture),
         };
+        self.entities.insert(label.to_owned(), entity);
+        self.draw_dirty = true;
+    }
+
+    // Draws `shape` every frame until it's removed or replaced by another shape with the same
+    // label. Shapes don't have entities, they're moved by setting them again.
+    pub fn set_shape(&mut self, label: &str, shape: Shape) {
+        self.shapes.insert(label.to_owned(), Arc::new(shape));
+        self.shapes_dirty = true;
+    }
+
+    pub fn remove_shape(&mut self, label: &str) {
+        if self.shapes.remove(label).is_some() {
+            self.shapes_dirty = true;
+        }
+    }
+
+    // Makes a TrueType or OpenType font available to `Text` under `name`, replacing any font that
+    // was loaded under it before
+    pub fn load_font(&mut self, name: &str, bytes: &[u8]) -> Result<(), &'static str> {
+        let font = Font::from_bytes(bytes.to_vec()).map_err(|_| "Unable to read font")?;
+        self.fonts.insert(name.to_owned(), Arc::new(font));
+        self.fonts_dirty = true;
+        Ok(())
+    }
+
+    // Changes what an entity connected with `connect_text` says, it's laid out again before the
+    // next frame
+    pub fn set_text(&mut self, label: &str, text: Text) {
+        let texture = match self.texture(label) {
+            Some(t) => t.clone(),
+            None => return,
+        };
+        {
+            let mut t = texture.lock().unwrap();
+            match &t.text {
+                None => {
+                    eprintln!("Not setting the text of {}, it isn't connected as text", label);
+                    return;
+                }
+                Some(current) if *current == text => return,
+                Some(_) => t.text = Some(text),
+            }
+        }
+        if !self.pending_loads.iter().any(|(l, _)| l == label) {
+            self.pending_loads.push((label.to_owned(), texture));
+        }
+    }
+
+    // Where the text's glyphs would go, None if its font isn't loaded
+    pub fn layout_text(&self, text: &Text) -> Option<TextLayout> {
+        self.fonts.get(&text.font).map(|font| text.layout(font))
+    }
+
+    // Starts drawing and updating a disabled entity
+    pub fn enable(&mut self, label: &str) {
+        if let Some(t) = self.disabled_textures.remove(label) {
+            self.enabled_textures.insert(label.to_owned(), t);
+            self.draw_dirty = true;
+        }
+    }
+
+    // Stops drawing and updating an entity. Its texture stays on the GPU, so enabling it again
+    // is cheap.
+    pub fn disable(&mut self, label: &str) {
+        if let Some(t) = self.enabled_textures.remove(label) {
+            self.disabled_textures.insert(label.to_owned(), t);
+            self.draw_dirty = true;
+        }
+    }
+
+    // Makes `child` follow `parent`: the child's matrix becomes relative to the parent's top-left
+    // corner and picks up its rotation, scale, skew and flip. If the parent is despawned the
+    // child is back to being positioned on its own.
+    pub fn attach(&mut self, child: &str, parent: &str) {
+        let (child_t, parent_t) = match (self.texture(child), self.texture(parent)) {
+            (Some(c), Some(p)) => (c.clone(), p.clone()),
+            _ => return,
+        };
+        // A cycle would never finish building vertices
+        let mut next = Some(parent_t.clone());
+        while let Some(t) = next {
+            if Arc::ptr_eq(&t, &child_t) {
+                eprintln!("Not attaching {} to {}, it would become its own parent", child, parent);
+                return;
+            }
+            next = t.lock().unwrap().parent.as_ref().and_then(Weak::upgrade);
+        }
+        child_t.lock().unwrap().parent = Some(Arc::downgrade(&parent_t));
+    }
+
+    pub fn detach(&mut self, child: &str) {
+        if let Some(t) = self.texture(child) {
+            t.lock().unwrap().parent = None;
+        }
+    }
+
+    // The renderer holds on to despawned textures until its next frame, so children would
+    // otherwise keep following them until then
+    fn detach_children(&self, parent: &Arc<Mutex<Texture>>) {
+        for t in self
+            .enabled_textures
+            .values()
+            .chain(self.disabled_textures.values())
+        {
+            let mut t = t.lock().unwrap();
+            let attached = t.parent.as_ref().and_then(Weak::upgrade);
+            if attached.map_or(false, |p| Arc::ptr_eq(&p, parent)) {
+                t.parent = None;
+            }
+        }
+    }
+
+    fn texture(&self, label: &str) -> Option<&Arc<Mutex<Texture>>> {
+        self.enabled_textures
+            .get(label)
+            .or_else(|| self.disabled_textures.get(label))
+    }
+
+    // Removes a connected entity and its texture, calling `Entity::teardown`. Anything attached
+    // to it is detached.
+    pub fn despawn(&mut self, label: &str) {
+        self.pending_loads.retain(|(l, _)| l != label);
+        if let Some(t) = self.enabled_textures.remove(label) {
+            self.detach_children(&t);
+            self.despawned.push(t);
+            self.draw_dirty = true;
+        }
+        if let Some(t) = self.disabled_textures.remove(label) {
+            self.detach_children(&t);
+            self.despawned.push(t);
+        }
+        if let Some(mut entity) = self.entities.remove(label) {
+            entity.teardown();
+        }
     }
 }
 
@@ -67,13 +369,45 @@ pub struct VkSession {
     render_target: RenderTarget,
     render_pass: Arc<framebuffer::RenderPassAbstract + Send + Sync>,
     framebuffers: Vec<Arc<framebuffer::FramebufferAbstract + Send + Sync>>,
-    draw_pipeline: Arc<DrawGraphicsPipeline>,
+    draw_pipelines: HashMap<BlendMode, Arc<DrawGraphicsPipeline>>,
+    shape_pipeline: Arc<ShapeGraphicsPipeline>,
+    sort_mode: SortMode,
+    // Copied from the game every frame
+    camera: Camera,
+    resolution: Option<VirtualResolution>,
+    // Kept around so the per-frame vertex data doesn't have to be reallocated
+    vertices: Vec<vertex::Vertex>,
+    vertex_pools: main::pool::FramePools<vertex::Vertex>,
+    // Revisions of the static textures drawn this frame, and where they are in the draw buffer
+    static_revisions: Vec<usize>,
+    static_sources: Vec<usize>,
+    // Vertices of the static batch being uploaded
+    static_vertices: Vec<vertex::Vertex>,
+    // Device local buffers for static batches, keyed by the revision of their first texture. Along
+    // with the revisions of all their textures and the frame they were last drawn in.
+    static_batches: HashMap<usize, (Vec<usize>, Arc<ImmutableBuffer<[vertex::Vertex]>>, u64)>,
+    frame: u64,
+    samplers: HashMap<SamplerOptions, Arc<Sampler>>,
+    // Copied from the game whenever they change, ordered by layer
+    shapes: Vec<Arc<Shape>>,
+    shape_vertices: Vec<vertex::ShapeVertex>,
+    shape_pools: main::pool::FramePools<vertex::ShapeVertex>,
+    fonts: HashMap<String, Arc<Font<'static>>>,
+    // Every text texture, so they can all be laid out again when the glyph cache is cleared
+    texts: HashMap<String, Weak<Mutex<Texture>>>,
+    glyph_cache: text::GlyphCache,
+    glyph_set: Option<Arc<DescriptorSet + Send + Sync>>,
 }
 pub type DrawGraphicsPipeline = pipeline::GraphicsPipeline<
     pipeline::vertex::SingleBufferDefinition<vertex::Vertex>,
     Box<vulkano::descriptor::PipelineLayoutAbstract + Send + Sync>,
     Arc<RenderPassAbstract + Send + Sync>,
 >;
+pub type ShapeGraphicsPipeline = pipeline::GraphicsPipeline<
+    pipeline::vertex::SingleBufferDefinition<vertex::ShapeVertex>,
+    Box<vulkano::descriptor::PipelineLayoutAbstract + Send + Sync>,
+    Arc<RenderPassAbstract + Send + Sync>,
+>;
 //pub type DrawGraphicsPipeline = Arc<pipeline::GraphicsPipelineAbstract + Send + Sync>;
 
 struct RenderTarget {
@@ -85,7 +419,7 @@ struct RenderTarget {
 }
 
 impl VkSession {
-    pub fn run<S>(game: Game<S>) -> Result<(), &'static str> {
+    pub fn run<S: Send + 'static>(game: Game<S>) -> Result<S, &'static str> {
         let instance = init::new_instance();
 
         println!("Listing discovered devices");
@@ -120,7 +454,8 @@ impl VkSession {
 
         let render_pass = init::render_pass(device.clone(), swapchain.clone());
 
-        let draw_pipeline = init::graphics_pipeline(device.clone(), render_pass.clone());
+        let draw_pipelines = init::graphics_pipelines(device.clone(), render_pass.clone());
+        let shape_pipeline = init::shape_pipeline(device.clone(), render_pass.clone());
 
         let framebuffer = images
             .iter()
@@ -145,6 +480,9 @@ impl VkSession {
             }
         };
 
+        let vertex_pools = main::pool::FramePools::new(device.clone(), images.len());
+        let shape_pools = main::pool::FramePools::new(device.clone(), images.len());
+
         let mut vk = VkSession {
             // instance: instance,
             device: device,
@@ -162,12 +500,29 @@ impl VkSession {
             },
             render_pass: render_pass,
             framebuffers: framebuffer,
-            draw_pipeline: draw_pipeline,
+            draw_pipelines: draw_pipelines,
+            shape_pipeline: shape_pipeline,
+            sort_mode: SortMode::Layer,
+            camera: Camera::new(),
+            resolution: None,
+            vertices: Vec::new(),
+            vertex_pools: vertex_pools,
+            static_revisions: Vec::new(),
+            static_sources: Vec::new(),
+            static_vertices: Vec::new(),
+            static_batches: HashMap::new(),
+            frame: 0,
+            samplers: HashMap::new(),
+            shapes: Vec::new(),
+            shape_vertices: Vec::new(),
+            shape_pools: shape_pools,
+            fonts: HashMap::new(),
+            texts: HashMap::new(),
+            glyph_cache: text::GlyphCache::new(),
+            glyph_set: None,
         };
         vk.recreate_dimensions_dependent().unwrap();
-        vk.vk_main(game);
-
-        Ok(())
+        Ok(vk.vk_main(game))
     }
 
     pub fn recreate_dimensions_dependent(&mut self) -> Result<(), ()> {
@@ -225,21 +580,84 @@ impl VkSession {
             })
             .collect::<Vec<_>>();
 
-        let vs = shader::vs::Shader::load(self.device.clone()).unwrap();
-        let fs = shader::fs::Shader::load(self.device.clone()).unwrap();
-        self.draw_pipeline = Arc::new(
-            pipeline::GraphicsPipeline::start()
-                .vertex_input_single_buffer::<vertex::Vertex>()
-                .vertex_shader(vs.main_entry_point(), ())
-                .triangle_strip()
-                .viewports_dynamic_scissors_irrelevant(1)
-                .fragment_shader(fs.main_entry_point(), ())
-                .blend_alpha_blending()
-                .render_pass(framebuffer::Subpass::from(self.render_pass.clone(), 0).unwrap())
-                .build(self.device.clone())
-                .unwrap(),
-        );
+        self.draw_pipelines =
+            init::graphics_pipelines(self.device.clone(), self.render_pass.clone());
+        self.shape_pipeline = init::shape_pipeline(self.device.clone(), self.render_pass.clone());
 
         Ok(())
     }
+
+    // Samplers are created the first time their options are used
+    fn sampler(&mut self, options: &SamplerOptions) -> Arc<Sampler> {
+        let device = self.device.clone();
+        self.samplers
+            .entry(*options)
+            .or_insert_with(|| main::draw::create_sampler(device, options))
+            .clone()
+    }
+
+    // Any of them will do for creating descriptor sets
+    fn draw_pipeline(&self, blend: BlendMode) -> Arc<DrawGraphicsPipeline> {
+        self.draw_pipelines[&blend].clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use entity::EntityContext;
+
+    struct Idle;
+
+    impl Entity<()> for Idle {
+        fn init(&mut self, _: &mut EntityContext<()>) {}
+        fn update(&mut self, _: &mut EntityContext<()>, _: f32) {}
+    }
+
+    fn connect(game: &mut Game<()>, label: &str, pos: (f32, f32)) {
+        let matrix = Matrix::pixels(pos).with_size((10.0, 10.0));
+        game.connect(label, matrix, &[], Box::new(Idle), true);
+    }
+
+    fn parent_of(game: &Game<()>, label: &str) -> Option<Arc<Mutex<Texture>>> {
+        let t = game.texture(label).unwrap().lock().unwrap();
+        t.parent.as_ref().and_then(Weak::upgrade)
+    }
+
+    fn top_left(game: &Game<()>, label: &str) -> (f32, f32) {
+        let t = game.texture(label).unwrap().lock().unwrap();
+        t.world_transform((800.0, 600.0)).apply((0.0, 0.0))
+    }
+
+    #[test]
+    fn attaching_in_a_cycle_is_rejected() {
+        let mut game = Game::new(());
+        connect(&mut game, "a", (0.0, 0.0));
+        connect(&mut game, "b", (0.0, 0.0));
+        connect(&mut game, "c", (0.0, 0.0));
+        game.attach("a", "b");
+        game.attach("b", "c");
+
+        game.attach("c", "a");
+        game.attach("b", "a");
+        game.attach("a", "a");
+        assert!(parent_of(&game, "c").is_none());
+        let b = game.texture("b").unwrap().clone();
+        assert!(Arc::ptr_eq(&parent_of(&game, "a").unwrap(), &b));
+        let c = game.texture("c").unwrap().clone();
+        assert!(Arc::ptr_eq(&parent_of(&game, "b").unwrap(), &c));
+    }
+
+    #[test]
+    fn child_of_a_despawned_parent_is_positioned_on_its_own() {
+        let mut game = Game::new(());
+        connect(&mut game, "parent", (100.0, 200.0));
+        connect(&mut game, "child", (5.0, 5.0));
+        game.attach("child", "parent");
+        assert_eq!(top_left(&game, "child"), (105.0, 205.0));
+
+        game.despawn("parent");
+        assert!(parent_of(&game, "child").is_none());
+        assert_eq!(top_left(&game, "child"), (5.0, 5.0));
+    }
 }
@@ -0,0 +1,106 @@
+// How a texture is loaded and sampled, see `Game::connect_with`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct TextureOptions {
+    pub sampler: SamplerOptions,
+    // Uploads smaller versions of the image along with it, which look a lot less noisy when the
+    // texture is drawn much smaller than the image. Takes a third more memory. In an atlas the
+    // padding only protects the first few levels from bleeding.
+    pub mipmaps: bool,
+    // The image's colors have already been multiplied with its alpha, so they're left as they are
+    // instead of being multiplied again when the image is loaded
+    pub premultiplied: bool,
+}
+
+impl TextureOptions {
+    // Sharp pixels when scaled up, see `SamplerOptions::pixel_art`
+    pub fn pixel_art() -> Self {
+        TextureOptions {
+            sampler: SamplerOptions::pixel_art(),
+            mipmaps: false,
+            premultiplied: false,
+        }
+    }
+
+    // Mipmaps with linear filtering between levels
+    pub fn mipmapped() -> Self {
+        TextureOptions {
+            sampler: SamplerOptions {
+                mipmap_mode: MipmapMode::Linear,
+                ..SamplerOptions::default()
+            },
+            mipmaps: true,
+            premultiplied: false,
+        }
+    }
+}
+
+// Textures with equal options share a sampler
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerOptions {
+    // Used when the texture is drawn larger than its image
+    pub mag_filter: Filter,
+    // Used when the texture is drawn smaller than its image
+    pub min_filter: Filter,
+    // How levels are picked when the texture has mipmaps, see `TextureOptions::mipmaps`
+    pub mipmap_mode: MipmapMode,
+    // Horizontal and vertical
+    pub address_mode: (AddressMode, AddressMode),
+    // Color outside of the image for `AddressMode::ClampToBorder`
+    pub border_color: BorderColor,
+    // 1 turns anisotropic filtering off. Clamped to what the device supports.
+    pub max_anisotropy: u32,
+}
+
+impl Default for SamplerOptions {
+    fn default() -> Self {
+        SamplerOptions {
+            mag_filter: Filter::Linear,
+            min_filter: Filter::Linear,
+            mipmap_mode: MipmapMode::Nearest,
+            address_mode: (AddressMode::Repeat, AddressMode::Repeat),
+            border_color: BorderColor::TransparentBlack,
+            max_anisotropy: 1,
+        }
+    }
+}
+
+impl SamplerOptions {
+    // Nearest filtering that doesn't wrap around at the edges
+    pub fn pixel_art() -> Self {
+        SamplerOptions {
+            mag_filter: Filter::Nearest,
+            min_filter: Filter::Nearest,
+            mipmap_mode: MipmapMode::Nearest,
+            address_mode: (AddressMode::ClampToEdge, AddressMode::ClampToEdge),
+            ..SamplerOptions::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MipmapMode {
+    Nearest,
+    Linear,
+}
+
+// What's sampled outside of 0..1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddressMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    ClampToBorder,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BorderColor {
+    TransparentBlack,
+    OpaqueBlack,
+    OpaqueWhite,
+}
@@ -0,0 +1,139 @@
+use crate::renderer::entity::Rect;
+
+// Renders as if the window was always `size` pixels, scaled to the actual window by `policy`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VirtualResolution {
+    pub size: (u32, u32),
+    pub policy: ScalePolicy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScalePolicy {
+    // Fills the window, distorting everything if its aspect ratio differs
+    Stretch,
+    // As large as fits while keeping the aspect ratio, with black bars on the sides
+    Fit,
+    // Like `Fit`, but only scaled by whole numbers so every pixel stays the same size. Smaller
+    // windows than `size` still get a scale of 1.
+    IntegerScale,
+    // Like `Fit`, but instead of black bars more of the world is shown
+    Expand,
+}
+
+// Where things end up in the window, all in the units the window was measured in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ScreenLayout {
+    // Part of the window that's drawn to
+    pub viewport: Rect,
+    // Part of the window that 0..1 maps to
+    pub area: Rect,
+    // Size of `area` as far as `Matrix` and the camera are concerned
+    pub units: (f32, f32),
+}
+
+impl ScreenLayout {
+    pub fn new(resolution: Option<VirtualResolution>, window: (f32, f32)) -> Self {
+        let whole = Rect::new(0.0, 0.0, window.0, window.1);
+        let resolution = match resolution {
+            Some(r) if r.size.0 > 0 && r.size.1 > 0 => r,
+            _ => {
+                return ScreenLayout {
+                    viewport: whole,
+                    area: whole,
+                    units: window,
+                }
+            }
+        };
+        let units = (resolution.size.0 as f32, resolution.size.1 as f32);
+
+        let fit = (window.0 / units.0).min(window.1 / units.1);
+        let scale = match resolution.policy {
+            ScalePolicy::IntegerScale => fit.floor().max(1.0),
+            _ => fit,
+        };
+        let size = (units.0 * scale, units.1 * scale);
+        let centered = Rect::new(
+            ((window.0 - size.0) / 2.0).floor(),
+            ((window.1 - size.1) / 2.0).floor(),
+            size.0,
+            size.1,
+        );
+
+        let (viewport, area) = match resolution.policy {
+            ScalePolicy::Stretch => (whole, whole),
+            ScalePolicy::Fit | ScalePolicy::IntegerScale => (centered, centered),
+            ScalePolicy::Expand => (whole, centered),
+        };
+        ScreenLayout {
+            viewport: viewport,
+            area: area,
+            units: units,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(policy: ScalePolicy, window: (f32, f32)) -> ScreenLayout {
+        let resolution = VirtualResolution {
+            size: (320, 240),
+            policy: policy,
+        };
+        ScreenLayout::new(Some(resolution), window)
+    }
+
+    #[test]
+    fn without_a_resolution_the_window_is_used() {
+        let layout = ScreenLayout::new(None, (1000.0, 600.0));
+        assert_eq!(layout.viewport, Rect::new(0.0, 0.0, 1000.0, 600.0));
+        assert_eq!(layout.area, layout.viewport);
+        assert_eq!(layout.units, (1000.0, 600.0));
+    }
+
+    #[test]
+    fn stretch_fills_the_window() {
+        let layout = layout(ScalePolicy::Stretch, (1000.0, 600.0));
+        assert_eq!(layout.viewport, Rect::new(0.0, 0.0, 1000.0, 600.0));
+        assert_eq!(layout.area, layout.viewport);
+        assert_eq!(layout.units, (320.0, 240.0));
+    }
+
+    #[test]
+    fn fit_keeps_the_aspect_ratio() {
+        let layout = layout(ScalePolicy::Fit, (1000.0, 600.0));
+        assert_eq!(layout.viewport, Rect::new(100.0, 0.0, 800.0, 600.0));
+        assert_eq!(layout.area, layout.viewport);
+        assert_eq!(layout.units, (320.0, 240.0));
+    }
+
+    #[test]
+    fn integer_scale_rounds_down() {
+        let layout = layout(ScalePolicy::IntegerScale, (1000.0, 600.0));
+        assert_eq!(layout.viewport, Rect::new(180.0, 60.0, 640.0, 480.0));
+        assert_eq!(layout.area, layout.viewport);
+
+        // Never below 1, even if that doesn't fit
+        let small = self::layout(ScalePolicy::IntegerScale, (200.0, 100.0));
+        assert_eq!(small.viewport, Rect::new(-60.0, -70.0, 320.0, 240.0));
+    }
+
+    #[test]
+    fn expand_draws_to_the_whole_window() {
+        let layout = layout(ScalePolicy::Expand, (1000.0, 600.0));
+        assert_eq!(layout.viewport, Rect::new(0.0, 0.0, 1000.0, 600.0));
+        assert_eq!(layout.area, Rect::new(100.0, 0.0, 800.0, 600.0));
+        assert_eq!(layout.units, (320.0, 240.0));
+    }
+
+    #[test]
+    fn empty_resolutions_are_ignored() {
+        let resolution = VirtualResolution {
+            size: (0, 240),
+            policy: ScalePolicy::Fit,
+        };
+        let layout = ScreenLayout::new(Some(resolution), (1000.0, 600.0));
+        assert_eq!(layout.units, (1000.0, 600.0));
+    }
+}
@@ -5,18 +5,21 @@ pub mod vs {
 #version 450
 
 layout(location = 0) in vec2 position;
-layout(location = 1) in vec2 scale;
+layout(location = 1) in vec2 uv;
+layout(location = 2) in vec4 color;
 layout(location = 0) out vec2 tex_coords;
+layout(location = 1) out vec4 tint;
 
-vec2 to_vk_numbers(vec2 n) {
-    return (n * 2 ) - vec2(1.0);
-}
+layout(push_constant) uniform PushConstants {
+    // World to clip space, see `Camera::view`
+    mat4 view;
+} pc;
 
 void main() {
-    vec2 n = to_vk_numbers(position);
-    gl_Position = vec4(n, 0.0, 1.0);
+    gl_Position = pc.view * vec4(position, 0.0, 1.0);
 
-    tex_coords = to_vk_numbers(n);
+    tex_coords = uv;
+    tint = color;
 }"
     }
 }
@@ -28,6 +31,7 @@ pub mod fs {
 #version 450
 
 layout(location = 0) in vec2 tex_coords;
+layout(location = 1) in vec4 tint;
 layout(location = 0) out vec4 f_color;
 
 layout(set = 0, binding = 0) uniform sampler2D tex;
@@ -35,7 +39,45 @@ layout(set = 0, binding = 0) uniform sampler2D tex;
 void main() {
     f_color = texture(
         tex, tex_coords
-    );
+    ) * tint;
+}
+"
+    }
+}
+
+pub mod shape_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+#version 450
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec4 color;
+layout(location = 0) out vec4 v_color;
+
+layout(push_constant) uniform PushConstants {
+    // Pixels to clip space
+    mat4 view;
+} pc;
+
+void main() {
+    gl_Position = pc.view * vec4(position, 0.0, 1.0);
+    v_color = color;
+}"
+    }
+}
+
+pub mod shape_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+
+layout(location = 0) in vec4 v_color;
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    f_color = vec4(v_color.rgb * v_color.a, v_color.a);
 }
 "
     }
@@ -0,0 +1,423 @@
+use crate::renderer::entity::Rect;
+use crate::renderer::vertex::ShapeVertex;
+use std::f32::consts::PI;
+
+// Most segments a circle is split into, small ones get fewer
+const MAX_CIRCLE_SEGMENTS: usize = 128;
+const MIN_CIRCLE_SEGMENTS: usize = 12;
+
+// Joins sharper than this get cut off instead of growing a long spike, as a multiple of the
+// line's thickness
+const MITER_LIMIT: f32 = 2.0;
+
+// Colored triangles drawn without a texture, see `Game::set_shape`.
+//
+// All positions are in pixels (virtual pixels with a virtual resolution), like `Units::Pixels`.
+// Shapes can be combined with `add` to draw them as one.
+#[derive(Debug, Clone)]
+pub struct Shape {
+    // Triangle list
+    pub vertices: Vec<ShapeVertex>,
+    // Drawn on top of textures in the same layer
+    pub layer: i32,
+    // See `Matrix::screen_space`
+    pub screen_space: bool,
+}
+
+impl Shape {
+    // Every three vertices make a triangle, with colors blended across it
+    pub fn from_triangles(vertices: Vec<ShapeVertex>) -> Self {
+        Shape {
+            vertices: vertices,
+            layer: 0,
+            screen_space: false,
+        }
+    }
+
+    fn empty() -> Self {
+        Shape::from_triangles(Vec::new())
+    }
+
+    pub fn with_layer(mut self, layer: i32) -> Self {
+        self.layer = layer;
+        self
+    }
+
+    pub fn in_screen_space(mut self) -> Self {
+        self.screen_space = true;
+        self
+    }
+
+    // Draws `other` on top of this shape
+    pub fn add(mut self, other: Shape) -> Self {
+        self.vertices.extend(other.vertices);
+        self
+    }
+
+    pub fn rect(rect: Rect, color: [f32; 4]) -> Self {
+        let mut shape = Shape::empty();
+        shape.quad(
+            [
+                (rect.x, rect.y),
+                (rect.x + rect.width, rect.y),
+                (rect.x + rect.width, rect.y + rect.height),
+                (rect.x, rect.y + rect.height),
+            ],
+            color,
+        );
+        shape
+    }
+
+    // The outline is drawn inside of `rect`
+    pub fn rect_outline(rect: Rect, thickness: f32, color: [f32; 4]) -> Self {
+        let t = thickness.min(rect.width / 2.0).min(rect.height / 2.0);
+        let inner = rect.height - t * 2.0;
+        Shape::rect(Rect::new(rect.x, rect.y, rect.width, t), color)
+            .add(Shape::rect(
+                Rect::new(rect.x, rect.y + rect.height - t, rect.width, t),
+                color,
+            ))
+            .add(Shape::rect(Rect::new(rect.x, rect.y + t, t, inner), color))
+            .add(Shape::rect(
+                Rect::new(rect.x + rect.width - t, rect.y + t, t, inner),
+                color,
+            ))
+    }
+
+    pub fn circle(center: (f32, f32), radius: f32, color: [f32; 4]) -> Self {
+        Shape::pie(center, radius, 0.0, PI * 2.0, color)
+    }
+
+    // Centered on the circle's edge
+    pub fn circle_outline(
+        center: (f32, f32),
+        radius: f32,
+        thickness: f32,
+        color: [f32; 4],
+    ) -> Self {
+        Shape::arc(center, radius, 0.0, PI * 2.0, thickness, color)
+    }
+
+    // Filled slice of a circle from angle `start` to `end`, clockwise in radians starting at the
+    // right
+    pub fn pie(center: (f32, f32), radius: f32, start: f32, end: f32, color: [f32; 4]) -> Self {
+        let segments = arc_segments(radius, end - start);
+        let points = arc_points(center, radius, start, end, segments);
+        let mut shape = Shape::empty();
+        for pair in points.windows(2) {
+            shape.triangle([center, pair[0], pair[1]], color);
+        }
+        shape
+    }
+
+    // Outline of a circle from angle `start` to `end`, see `pie`
+    pub fn arc(
+        center: (f32, f32),
+        radius: f32,
+        start: f32,
+        end: f32,
+        thickness: f32,
+        color: [f32; 4],
+    ) -> Self {
+        let outer_radius = radius + thickness / 2.0;
+        let inner_radius = (radius - thickness / 2.0).max(0.0);
+        let segments = arc_segments(outer_radius, end - start);
+        let outer = arc_points(center, outer_radius, start, end, segments);
+        let inner = arc_points(center, inner_radius, start, end, segments);
+        let mut shape = Shape::empty();
+        for i in 0..segments {
+            shape.quad([outer[i], outer[i + 1], inner[i + 1], inner[i]], color);
+        }
+        shape
+    }
+
+    pub fn line(from: (f32, f32), to: (f32, f32), thickness: f32, color: [f32; 4]) -> Self {
+        Shape::polyline(&[from, to], thickness, false, color)
+    }
+
+    // Connected lines through all points, back to the first one if `closed`
+    pub fn polyline(points: &[(f32, f32)], thickness: f32, closed: bool, color: [f32; 4]) -> Self {
+        let mut shape = Shape::empty();
+        let n = points.len();
+        if n < 2 {
+            return shape;
+        }
+
+        // Both sides of the line at every point
+        let half = thickness / 2.0;
+        let sides = (0..n)
+            .map(|i| {
+                let prev = match (i, closed) {
+                    (0, false) => None,
+                    _ => Some(points[(i + n - 1) % n]),
+                };
+                let next = match (i == n - 1, closed) {
+                    (true, false) => None,
+                    _ => Some(points[(i + 1) % n]),
+                };
+                let offset = join_offset(prev, points[i], next, half);
+                (
+                    (points[i].0 + offset.0, points[i].1 + offset.1),
+                    (points[i].0 - offset.0, points[i].1 - offset.1),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let segments = if closed { n } else { n - 1 };
+        for i in 0..segments {
+            let (a, b) = (sides[i], sides[(i + 1) % n]);
+            shape.quad([a.0, b.0, b.1, a.1], color);
+        }
+        shape
+    }
+
+    // Filled polygon, which may be concave but shouldn't cross itself
+    pub fn polygon(points: &[(f32, f32)], color: [f32; 4]) -> Self {
+        let colored = points.iter().map(|&p| (p, color)).collect::<Vec<_>>();
+        Shape::polygon_colored(&colored)
+    }
+
+    // Like `polygon`, with colors blended between the corners
+    pub fn polygon_colored(points: &[((f32, f32), [f32; 4])]) -> Self {
+        let positions = points.iter().map(|p| p.0).collect::<Vec<_>>();
+        let vertices = triangulate(&positions)
+            .into_iter()
+            .map(|i| ShapeVertex {
+                position: [points[i].0 .0, points[i].0 .1],
+                color: points[i].1,
+            })
+            .collect();
+        Shape::from_triangles(vertices)
+    }
+
+    pub fn polygon_outline(points: &[(f32, f32)], thickness: f32, color: [f32; 4]) -> Self {
+        Shape::polyline(points, thickness, true, color)
+    }
+
+    fn triangle(&mut self, corners: [(f32, f32); 3], color: [f32; 4]) {
+        self.vertices.extend(corners.iter().map(|p| ShapeVertex {
+            position: [p.0, p.1],
+            color: color,
+        }));
+    }
+
+    // Corners in order around the quad
+    fn quad(&mut self, c: [(f32, f32); 4], color: [f32; 4]) {
+        self.triangle([c[0], c[1], c[2]], color);
+        self.triangle([c[0], c[2], c[3]], color);
+    }
+}
+
+// Enough to look round at that radius
+fn arc_segments(radius: f32, sweep: f32) -> usize {
+    let full = ((radius.max(0.0).sqrt() * 8.0) as usize)
+        .max(MIN_CIRCLE_SEGMENTS)
+        .min(MAX_CIRCLE_SEGMENTS);
+    ((full as f32 * sweep.abs() / (PI * 2.0)).ceil() as usize).max(1)
+}
+
+fn arc_points(
+    center: (f32, f32),
+    radius: f32,
+    start: f32,
+    end: f32,
+    segments: usize,
+) -> Vec<(f32, f32)> {
+    let sweep = end - start;
+    (0..=segments)
+        .map(|i| {
+            let angle = start + sweep * i as f32 / segments as f32;
+            (
+                center.0 + radius * angle.cos(),
+                center.1 + radius * angle.sin(),
+            )
+        })
+        .collect()
+}
+
+fn normal(from: (f32, f32), to: (f32, f32)) -> (f32, f32) {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let len = (dx * dx + dy * dy).sqrt();
+    match len > 0.0 {
+        true => (-dy / len, dx / len),
+        false => (0.0, 0.0),
+    }
+}
+
+// How far the edges of a line `half` thick are from `point`, with the segments on either side
+// meeting in a miter
+fn join_offset(
+    prev: Option<(f32, f32)>,
+    point: (f32, f32),
+    next: Option<(f32, f32)>,
+    half: f32,
+) -> (f32, f32) {
+    let n = match (prev, next) {
+        (Some(prev), Some(next)) => {
+            let (a, b) = (normal(prev, point), normal(point, next));
+            let sum = (a.0 + b.0, a.1 + b.1);
+            let len = (sum.0 * sum.0 + sum.1 * sum.1).sqrt();
+            if len == 0.0 {
+                return (a.0 * half, a.1 * half);
+            }
+            let miter = (sum.0 / len, sum.1 / len);
+            // Length of the miter compared to a straight line
+            let scale = 1.0 / (miter.0 * a.0 + miter.1 * a.1).max(1.0 / MITER_LIMIT);
+            (miter.0 * scale, miter.1 * scale)
+        }
+        (Some(prev), None) => normal(prev, point),
+        (None, Some(next)) => normal(point, next),
+        (None, None) => (0.0, 0.0),
+    };
+    (n.0 * half, n.1 * half)
+}
+
+// Ear clipping, returns indices into `points` with three per triangle
+fn triangulate(points: &[(f32, f32)]) -> Vec<usize> {
+    let mut triangles = Vec::new();
+    if points.len() < 3 {
+        return triangles;
+    }
+
+    // Works on counter-clockwise polygons (in y-down screen space), so clockwise ones are walked
+    // backwards
+    let area: f32 = (0..points.len())
+        .map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % points.len()]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum();
+    let mut remaining = (0..points.len()).collect::<Vec<_>>();
+    if area > 0.0 {
+        remaining.reverse();
+    }
+
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = (
+                remaining[(i + n - 1) % n],
+                remaining[i],
+                remaining[(i + 1) % n],
+            );
+            cross(points[a], points[b], points[c]) < 0.0
+                && !remaining.iter().any(|&p| {
+                    p != a
+                        && p != b
+                        && p != c
+                        && in_triangle(points[p], points[a], points[b], points[c])
+                })
+        });
+        // Only happens with degenerate or self intersecting polygons, clip something anyway so
+        // this ends
+        let i = ear.unwrap_or(0);
+        triangles.extend(&[
+            remaining[(i + n - 1) % n],
+            remaining[i],
+            remaining[(i + 1) % n],
+        ]);
+        remaining.remove(i);
+    }
+    triangles.extend(&remaining);
+    triangles
+}
+
+fn cross(a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> f32 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+fn in_triangle(p: (f32, f32), a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> bool {
+    let (d1, d2, d3) = (cross(a, b, p), cross(b, c, p), cross(c, a, p));
+    let negative = d1 < 0.0 || d2 < 0.0 || d3 < 0.0;
+    let positive = d1 > 0.0 || d2 > 0.0 || d3 > 0.0;
+    !(negative && positive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area(points: &[(f32, f32)]) -> f32 {
+        let n = points.len();
+        (0..n)
+            .map(|i| {
+                let (a, b) = (points[i], points[(i + 1) % n]);
+                a.0 * b.1 - b.0 * a.1
+            })
+            .sum::<f32>()
+            .abs()
+            / 2.0
+    }
+
+    fn triangulated_area(points: &[(f32, f32)]) -> f32 {
+        let indices = triangulate(points);
+        assert_eq!(indices.len(), (points.len() - 2) * 3);
+        indices
+            .chunks(3)
+            .map(|t| area(&[points[t[0]], points[t[1]], points[t[2]]]))
+            .sum()
+    }
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4
+    }
+
+    // An L with its inner corner at (1, 1)
+    const L: [(f32, f32); 6] = [
+        (0.0, 0.0),
+        (2.0, 0.0),
+        (2.0, 1.0),
+        (1.0, 1.0),
+        (1.0, 2.0),
+        (0.0, 2.0),
+    ];
+
+    #[test]
+    fn triangulates_concave_polygons() {
+        assert_eq!(triangulated_area(&L), 3.0);
+
+        // A star, every other corner pointing inwards
+        let star = (0..10)
+            .map(|i| {
+                let r = if i % 2 == 0 { 10.0 } else { 4.0 };
+                let angle = i as f32 * PI / 5.0;
+                (r * angle.cos(), r * angle.sin())
+            })
+            .collect::<Vec<_>>();
+        assert!((triangulated_area(&star) - area(&star)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn triangulates_either_winding() {
+        let mut reversed = L.to_vec();
+        reversed.reverse();
+        assert_eq!(triangulated_area(&reversed), 3.0);
+    }
+
+    #[test]
+    fn skips_polygons_without_area() {
+        assert!(triangulate(&[(0.0, 0.0), (1.0, 1.0)]).is_empty());
+        assert!(Shape::polygon(&[], [1.0; 4]).vertices.is_empty());
+    }
+
+    #[test]
+    fn straight_lines_are_offset_along_their_normal() {
+        let offset = join_offset(Some((0.0, 0.0)), (5.0, 0.0), Some((10.0, 0.0)), 2.0);
+        assert!(close(offset, (0.0, 2.0)));
+        let end = join_offset(Some((0.0, 0.0)), (0.0, 5.0), None, 2.0);
+        assert!(close(end, (-2.0, 0.0)));
+    }
+
+    #[test]
+    fn corners_meet_in_a_miter() {
+        let offset = join_offset(Some((0.0, 0.0)), (10.0, 0.0), Some((10.0, 10.0)), 1.0);
+        assert!(close(offset, (-1.0, 1.0)));
+    }
+
+    #[test]
+    fn sharp_corners_are_limited() {
+        let offset = join_offset(Some((0.0, 0.0)), (10.0, 0.0), Some((0.0, 0.5)), 1.0);
+        let len = (offset.0 * offset.0 + offset.1 * offset.1).sqrt();
+        assert!((len - MITER_LIMIT).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,342 @@
+use crate::renderer::atlas::Skyline;
+use hashbrown::HashMap;
+use image::{Rgba, RgbaImage};
+use rusttype::{point, Font, GlyphId, PositionedGlyph, Scale};
+use std::mem;
+
+// Width and height of the glyph cache texture
+const GLYPH_CACHE_SIZE: u32 = 1024;
+// Transparent pixels around every glyph, so filtering doesn't pick up its neighbours
+const GLYPH_PADDING: u32 = 1;
+// Glyphs are rasterized at this many horizontal offsets within a pixel
+const SUBPIXEL_STEPS: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    // Where a line starts within a block `width` wide
+    fn offset(self, width: f32, line_width: f32) -> f32 {
+        match self {
+            Align::Left => 0.0,
+            Align::Center => (width - line_width) / 2.0,
+            Align::Right => width - line_width,
+        }
+    }
+}
+
+// A block of text drawn with a font loaded by `Game::load_font`, see `Game::connect_text`.
+//
+// The block is placed by the entity's `Matrix` like an image of the block's size would be.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Text {
+    pub content: String,
+    pub font: String,
+    // Font size in pixels
+    pub size: f32,
+    // Straight alpha, tinted by `Matrix::color` like any other texture
+    pub color: [f32; 4],
+    // How lines are aligned within the block, which is as wide as `max_width` or the longest line
+    pub align: Align,
+    // Lines longer than this are wrapped at spaces, or anywhere in words that don't fit at all
+    pub max_width: Option<f32>,
+    // Multiple of the font's own line height
+    pub line_spacing: f32,
+}
+
+// Where the glyphs of a `Text` go, in pixels from the block's top-left corner
+pub struct TextLayout {
+    pub glyphs: Vec<PositionedGlyph<'static>>,
+    // Of the whole block
+    pub size: (f32, f32),
+}
+
+impl Text {
+    pub fn new(content: &str, font: &str, size: f32) -> Self {
+        Text {
+            content: content.to_owned(),
+            font: font.to_owned(),
+            size: size,
+            color: [1.0, 1.0, 1.0, 1.0],
+            align: Align::Left,
+            max_width: None,
+            line_spacing: 1.0,
+        }
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_align(mut self, align: Align) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub fn wrapped(mut self, max_width: f32) -> Self {
+        self.max_width = Some(max_width);
+        self
+    }
+
+    pub fn with_line_spacing(mut self, line_spacing: f32) -> Self {
+        self.line_spacing = line_spacing;
+        self
+    }
+
+    // Breaks the text into lines and places every glyph, kerning included
+    pub fn layout(&self, font: &Font<'static>) -> TextLayout {
+        let scale = Scale::uniform(self.size);
+        let v = font.v_metrics(scale);
+        let line_height = (v.ascent - v.descent + v.line_gap) * self.line_spacing;
+
+        let lines = self
+            .content
+            .split('\n')
+            .flat_map(|paragraph| match self.max_width {
+                Some(max) => wrap(paragraph, max, |s| line_glyphs(font, scale, s).1),
+                None => vec![paragraph.to_owned()],
+            })
+            .map(|line| line_glyphs(font, scale, &line))
+            .collect::<Vec<_>>();
+        let width = self
+            .max_width
+            .unwrap_or_else(|| lines.iter().map(|l| l.1).fold(0.0, f32::max));
+
+        let mut glyphs = Vec::new();
+        for (i, (line, line_width)) in lines.iter().enumerate() {
+            let x = self.align.offset(width, *line_width);
+            let y = v.ascent + i as f32 * line_height;
+            glyphs.extend(
+                line.iter()
+                    .map(|&(id, gx)| font.glyph(id).scaled(scale).positioned(point(x + gx, y))),
+            );
+        }
+
+        TextLayout {
+            glyphs: glyphs,
+            size: (
+                width,
+                (lines.len() - 1) as f32 * line_height + v.ascent - v.descent,
+            ),
+        }
+    }
+}
+
+// Glyphs of a single line starting at 0, along with how wide the line is
+fn line_glyphs(font: &Font<'static>, scale: Scale, line: &str) -> (Vec<(GlyphId, f32)>, f32) {
+    let mut glyphs = Vec::new();
+    let mut caret = 0.0;
+    let mut last = None;
+    for c in line.chars().filter(|c| !c.is_control()) {
+        let glyph = font.glyph(c).scaled(scale);
+        let id = glyph.id();
+        if let Some(last) = last {
+            caret += font.pair_kerning(scale, last, id);
+        }
+        glyphs.push((id, caret));
+        caret += glyph.h_metrics().advance_width;
+        last = Some(id);
+    }
+    (glyphs, caret)
+}
+
+// `width` measures a line
+fn wrap<F: Fn(&str) -> f32>(paragraph: &str, max_width: f32, width: F) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in paragraph.split(' ') {
+        let joined = match line.is_empty() {
+            true => word.to_owned(),
+            false => format!("{} {}", line, word),
+        };
+        match !line.is_empty() && width(&joined) > max_width {
+            true => lines.push(mem::replace(&mut line, word.to_owned())),
+            false => line = joined,
+        }
+
+        // Splits words that don't fit on a line of their own, keeping at least a character per
+        // line
+        while width(&line) > max_width && line.chars().count() > 1 {
+            let ends = line
+                .char_indices()
+                .skip(1)
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>();
+            let split = ends
+                .iter()
+                .rev()
+                .cloned()
+                .find(|&i| width(&line[..i]) <= max_width)
+                .unwrap_or(ends[0]);
+            let rest = line.split_off(split);
+            lines.push(mem::replace(&mut line, rest));
+        }
+    }
+    lines.push(line);
+    lines
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    font: usize,
+    id: GlyphId,
+    size: (u32, u32),
+    subpixel: u32,
+}
+
+// A glyph ready to be drawn, as (x0, y0, x1, y1) in pixels and texture coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct GlyphQuad {
+    pub rect: [f32; 4],
+    pub uv: [f32; 4],
+}
+
+// Rasterized glyphs sharing a single texture. Glyphs are added as text needs them and only go
+// away when the cache is cleared.
+pub(crate) struct GlyphCache {
+    image: RgbaImage,
+    packer: Skyline,
+    glyphs: HashMap<GlyphKey, [u32; 4]>,
+    // Set when the image changed, until it's uploaded again
+    pub dirty: bool,
+}
+
+impl GlyphCache {
+    pub fn new() -> Self {
+        GlyphCache {
+            image: RgbaImage::new(GLYPH_CACHE_SIZE, GLYPH_CACHE_SIZE),
+            packer: Skyline::new(GLYPH_CACHE_SIZE),
+            glyphs: HashMap::new(),
+            dirty: true,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = GlyphCache::new();
+    }
+
+    pub fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+
+    // Rasterizes the glyphs that aren't cached yet. `font` tells fonts apart and must stay the
+    // same for as long as the font is in use. None if the cache is full.
+    pub fn quads(
+        &mut self,
+        font: usize,
+        glyphs: &[PositionedGlyph<'static>],
+    ) -> Option<Vec<GlyphQuad>> {
+        let mut quads = Vec::new();
+        for g in glyphs {
+            // Rounded to whole pixels vertically and to a subpixel step horizontally, so the
+            // same glyph can be reused on every line
+            let pos = g.position();
+            let x = pos.x.floor();
+            let subpixel = ((pos.x - x) * SUBPIXEL_STEPS).floor();
+            let y = pos.y.round();
+
+            let glyph = g
+                .unpositioned()
+                .clone()
+                .positioned(point(subpixel / SUBPIXEL_STEPS, 0.0));
+            // Nothing to draw, like a space
+            let bounds = match glyph.pixel_bounding_box() {
+                Some(bounds) => bounds,
+                None => continue,
+            };
+            let scale = g.scale();
+            let key = GlyphKey {
+                font: font,
+                id: g.id(),
+                size: (scale.x.to_bits(), scale.y.to_bits()),
+                subpixel: subpixel as u32,
+            };
+            let spot = match self.glyphs.get(&key) {
+                Some(&spot) => spot,
+                None => {
+                    let spot = self.rasterize(&glyph)?;
+                    self.glyphs.insert(key, spot);
+                    spot
+                }
+            };
+
+            let size = GLYPH_CACHE_SIZE as f32;
+            quads.push(GlyphQuad {
+                rect: [
+                    x + bounds.min.x as f32,
+                    y + bounds.min.y as f32,
+                    x + bounds.max.x as f32,
+                    y + bounds.max.y as f32,
+                ],
+                uv: [
+                    spot[0] as f32 / size,
+                    spot[1] as f32 / size,
+                    spot[2] as f32 / size,
+                    spot[3] as f32 / size,
+                ],
+            });
+        }
+        Some(quads)
+    }
+
+    // Draws the glyph into free space, returning where it went as (x0, y0, x1, y1)
+    fn rasterize(&mut self, glyph: &PositionedGlyph<'static>) -> Option<[u32; 4]> {
+        let bounds = glyph.pixel_bounding_box()?;
+        let (w, h) = (bounds.width() as u32, bounds.height() as u32);
+        let (x, y) = self
+            .packer
+            .place(w + GLYPH_PADDING * 2, h + GLYPH_PADDING * 2)?;
+        let (x, y) = (x + GLYPH_PADDING, y + GLYPH_PADDING);
+
+        // White with premultiplied coverage, tinted by the vertex color
+        let image = &mut self.image;
+        glyph.draw(|gx, gy, coverage| {
+            let v = (coverage.min(1.0) * 255.0).round() as u8;
+            image.put_pixel(x + gx, y + gy, Rgba([v, v, v, v]));
+        });
+        self.dirty = true;
+        Some([x, y, x + w, y + h])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every character is 10 pixels wide
+    fn wrap10(paragraph: &str, max_width: f32) -> Vec<String> {
+        wrap(paragraph, max_width, |s| s.chars().count() as f32 * 10.0)
+    }
+
+    #[test]
+    fn wraps_at_spaces() {
+        assert_eq!(
+            wrap10("the quick brown fox", 100.0),
+            vec!["the quick", "brown fox"]
+        );
+        assert_eq!(wrap10("short", 100.0), vec!["short"]);
+        assert_eq!(wrap10("", 100.0), vec![""]);
+    }
+
+    #[test]
+    fn splits_words_that_dont_fit() {
+        assert_eq!(wrap10("a abcdefgh", 30.0), vec!["a", "abc", "def", "gh"]);
+    }
+
+    #[test]
+    fn keeps_a_character_per_line() {
+        assert_eq!(wrap10("abc", 5.0), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn aligns_lines_within_the_block() {
+        assert_eq!(Align::Left.offset(100.0, 40.0), 0.0);
+        assert_eq!(Align::Center.offset(100.0, 40.0), 30.0);
+        assert_eq!(Align::Right.offset(100.0, 40.0), 60.0);
+    }
+}
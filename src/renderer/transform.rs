@@ -0,0 +1,134 @@
+use std::ops::Mul;
+
+// 2D affine transform as a 3x3 matrix, applied to column vectors (x, y, 1).
+//
+// `a * b` applies `b` first, then `a`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub m: [[f32; 3]; 3],
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Transform {
+            m: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+
+    pub fn translate(x: f32, y: f32) -> Self {
+        Transform {
+            m: [[1.0, 0.0, x], [0.0, 1.0, y], [0.0, 0.0, 1.0]],
+        }
+    }
+
+    pub fn scale(x: f32, y: f32) -> Self {
+        Transform {
+            m: [[x, 0.0, 0.0], [0.0, y, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+
+    // Clockwise on screen, since y points down
+    pub fn rotate(radians: f32) -> Self {
+        let (sin, cos) = radians.sin_cos();
+        Transform {
+            m: [[cos, -sin, 0.0], [sin, cos, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+
+    // Shears by the given angles (in radians) along the x and y axis
+    pub fn skew(x: f32, y: f32) -> Self {
+        Transform {
+            m: [[1.0, x.tan(), 0.0], [y.tan(), 1.0, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+
+    // Undoes this transform. Transforms that squash everything onto a line or point (like a scale
+    // by 0) have no inverse, those give back the identity.
+    pub fn inverse(&self) -> Transform {
+        let m = &self.m;
+        let det = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+        if det == 0.0 {
+            return Transform::identity();
+        }
+        let (a, b, d, e) = (
+            m[1][1] / det,
+            -m[0][1] / det,
+            -m[1][0] / det,
+            m[0][0] / det,
+        );
+        Transform {
+            m: [
+                [a, b, -(a * m[0][2] + b * m[1][2])],
+                [d, e, -(d * m[0][2] + e * m[1][2])],
+                [0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    // Column major 4x4 matrix for shaders, leaving z alone
+    pub fn to_mat4(&self) -> [[f32; 4]; 4] {
+        let m = &self.m;
+        [
+            [m[0][0], m[1][0], 0.0, 0.0],
+            [m[0][1], m[1][1], 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [m[0][2], m[1][2], 0.0, 1.0],
+        ]
+    }
+
+    pub fn apply(&self, p: (f32, f32)) -> (f32, f32) {
+        let m = &self.m;
+        (
+            m[0][0] * p.0 + m[0][1] * p.1 + m[0][2],
+            m[1][0] * p.0 + m[1][1] * p.1 + m[1][2],
+        )
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, rhs: Transform) -> Transform {
+        let mut m = [[0.0; 3]; 3];
+        for row in 0..3 {
+            for col in 0..3 {
+                m[row][col] = (0..3).map(|i| self.m[row][i] * rhs.m[i][col]).sum();
+            }
+        }
+        Transform { m: m }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-3 && (a.1 - b.1).abs() < 1e-3
+    }
+
+    #[test]
+    fn inverse_undoes_the_transform() {
+        let t = Transform::translate(30.0, -12.0)
+            * Transform::rotate(0.7)
+            * Transform::skew(0.2, -0.1)
+            * Transform::scale(2.0, 0.5);
+        let inverse = t.inverse();
+        for &p in &[(0.0, 0.0), (1.0, 2.0), (-40.0, 17.5)] {
+            assert!(close(inverse.apply(t.apply(p)), p));
+            assert!(close(t.apply(inverse.apply(p)), p));
+        }
+    }
+
+    #[test]
+    fn inverse_of_a_flat_transform_is_the_identity() {
+        let t = Transform::scale(0.0, 1.0);
+        assert_eq!(t.inverse(), Transform::identity());
+    }
+
+    #[test]
+    fn applies_the_right_side_first() {
+        let t = Transform::translate(10.0, 0.0) * Transform::scale(2.0, 2.0);
+        assert!(close(t.apply((1.0, 1.0)), (12.0, 2.0)));
+    }
+}
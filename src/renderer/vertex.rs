@@ -1,9 +1,12 @@
 #[derive(Debug, Clone)]
 pub struct Vertex {
     pub position: [f32; 2],
-    pub scale: (f32, f32),
+    // Texture coordinates, 0..1 across the whole texture
+    pub uv: [f32; 2],
+    // Multiplied with the sampled texel, premultiplied by its own alpha
+    pub color: [f32; 4],
 }
-vulkano::impl_vertex!(Vertex, position, scale);
+vulkano::impl_vertex!(Vertex, position, uv, color);
 
 impl Vertex {
     pub fn square(pos: (f32, f32), size: (f32, f32)) -> [Vertex; 4] {
@@ -11,23 +14,37 @@ impl Vertex {
             Vertex {
                 // Top-Left
                 position: [pos.0, pos.1],
-                scale: (1.0, 1.0),
+                uv: [0.0, 0.0],
+                color: [1.0, 1.0, 1.0, 1.0],
             },
             Vertex {
                 // Bottom-Left
                 position: [pos.0, pos.1 + size.1],
-                scale: (1.0, 1.0),
+                uv: [0.0, 1.0],
+                color: [1.0, 1.0, 1.0, 1.0],
             },
             Vertex {
                 // Top-Right
                 position: [pos.0 + size.0, pos.1],
-                scale: (1.0, 1.0),
+                uv: [1.0, 0.0],
+                color: [1.0, 1.0, 1.0, 1.0],
             },
             Vertex {
                 // Bottom-Right
                 position: [pos.0 + size.0, pos.1 + size.1],
-                scale: (1.0, 1.0),
+                uv: [1.0, 1.0],
+                color: [1.0, 1.0, 1.0, 1.0],
             },
         ]
     }
 }
+
+// Corner of an untextured triangle, see `Shape`
+#[derive(Debug, Clone)]
+pub struct ShapeVertex {
+    // In pixels
+    pub position: [f32; 2],
+    // Straight alpha, premultiplied in the fragment shader
+    pub color: [f32; 4],
+}
+vulkano::impl_vertex!(ShapeVertex, position, color);